@@ -28,14 +28,24 @@ pub mod commonsafe;
 pub mod normal;
 pub mod wordsfi;
 
-impl From<crate::cli::Alphabet> for Box<dyn Alphabet> {
-    fn from(alphabet: crate::cli::Alphabet) -> Self {
+/// The alphabets bundled with keygen
+#[derive(clap::ValueEnum, Clone, Debug, Copy)]
+pub enum Builtin {
+    WordsFi,
+    Commonsafe,
+    Normal,
+    Ascii,
+    Assembly,
+}
+
+impl From<Builtin> for Box<dyn Alphabet> {
+    fn from(alphabet: Builtin) -> Self {
         match alphabet {
-            crate::cli::Alphabet::WordsFi => Box::new(wordsfi::WordsFi {}),
-            crate::cli::Alphabet::Commonsafe => Box::new(commonsafe::CommonSafe {}),
-            crate::cli::Alphabet::Normal => Box::new(normal::Normal {}),
-            crate::cli::Alphabet::Ascii => Box::new(ascii::Ascii {}),
-            crate::cli::Alphabet::Assembly => Box::new(assembly::Assembly {}),
+            Builtin::WordsFi => Box::new(wordsfi::WordsFi {}),
+            Builtin::Commonsafe => Box::new(commonsafe::CommonSafe {}),
+            Builtin::Normal => Box::new(normal::Normal {}),
+            Builtin::Ascii => Box::new(ascii::Ascii {}),
+            Builtin::Assembly => Box::new(assembly::Assembly {}),
         }
     }
 }
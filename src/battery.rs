@@ -0,0 +1,529 @@
+//! Statistical test battery for the random sources.
+//!
+//! Runs the power-up tests of FIPS 140-2 Chapter 4.9.1 (monobit, poker, runs and long run) on
+//! each 20,000 bit block of the data, and the byte tests of the `ent` program over all of it:
+//! chi-square of the byte frequencies, serial correlation and the Monte Carlo value of pi.
+//! <https://csrc.nist.gov/pubs/fips/140-2/upd2/final>
+//! <https://www.fourmilab.ch/random/>
+//!
+//! These tests catch broken sources, they can not show that a source is unpredictable.
+
+use std::fmt;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("At least {0} bytes are needed for the test battery")]
+    NotEnoughData(usize),
+    #[error("{0} tests of the battery failed")]
+    Failed(usize),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Size of the FIPS 140-2 test block, 20,000 bits.
+pub const FIPS_BLOCK_BYTES: usize = 2500;
+
+/// Exclusive bounds of the count of ones in a block. (FIPS 140-2 4.9.1)
+const MONOBIT_BOUNDS: (u32, u32) = (9725, 10275);
+
+/// Exclusive bounds of the poker test statistic.
+const POKER_BOUNDS: (f64, f64) = (2.16, 46.17);
+
+/// Inclusive bounds of the number of runs of length 1, 2, 3, 4, 5 and 6 or more.
+const RUN_BOUNDS: [(u32, u32); 6] = [
+    (2315, 2685),
+    (1114, 1386),
+    (527, 723),
+    (240, 384),
+    (103, 209),
+    (103, 209),
+];
+
+/// Length of the shortest run that fails the long run test.
+const LONG_RUN: u32 = 26;
+
+/// Two-sided 99.9% bound of the standard normal distribution.
+const Z_CRITICAL: f64 = 3.29;
+
+/// Lowest p-value of the chi-square test that passes, the highest is `1 - CHI_SQUARE_ALPHA`.
+const CHI_SQUARE_ALPHA: f64 = 0.001;
+
+/// The value a test computed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    /// FIPS 140-2 blocks that failed the test, out of [`Report::blocks`]
+    FailedBlocks(usize),
+    /// Chi-square of the byte frequencies with 255 degrees of freedom, and its p-value
+    ChiSquare { value: f64, p_value: f64 },
+    /// Serial correlation coefficient of successive bytes
+    SerialCorrelation(f64),
+    /// Monte Carlo estimate of pi
+    Pi(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name: &'static str,
+    pub statistic: Statistic,
+    pub passed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub bytes: usize,
+    /// Number of FIPS 140-2 blocks tested
+    pub blocks: usize,
+    pub outcomes: Vec<Outcome>,
+    /// Shannon entropy in bits per byte, informational
+    pub entropy: f64,
+    /// Arithmetic mean of the bytes, informational
+    pub mean: f64,
+}
+
+impl Report {
+    /// Returns the number of tests that failed.
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| !outcome.passed)
+            .count()
+    }
+
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.failures() == 0
+    }
+
+    /// Returns the report as a JSON object.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let outcomes: Vec<String> = self
+            .outcomes
+            .iter()
+            .map(|outcome| {
+                let statistic = match outcome.statistic {
+                    Statistic::FailedBlocks(failed) => format!("\"failed_blocks\":{failed}"),
+                    Statistic::ChiSquare { value, p_value } => {
+                        format!("\"chi_square\":{value},\"p_value\":{p_value}")
+                    }
+                    Statistic::SerialCorrelation(coefficient) => {
+                        format!("\"coefficient\":{coefficient}")
+                    }
+                    Statistic::Pi(pi) => format!("\"pi\":{pi}"),
+                };
+                format!(
+                    "{{\"name\":\"{}\",\"passed\":{},{statistic}}}",
+                    outcome.name, outcome.passed
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"passed\":{},\"bytes\":{},\"blocks\":{},\"entropy\":{},\"mean\":{},\"tests\":[{}]}}",
+            self.passed(),
+            self.bytes,
+            self.blocks,
+            self.entropy,
+            self.mean,
+            outcomes.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Bytes: {} ({} FIPS 140-2 blocks)",
+            self.bytes, self.blocks
+        )?;
+        for outcome in &self.outcomes {
+            let result = if outcome.passed { "pass" } else { "FAIL" };
+            write!(f, "{:<20} {result}  ", outcome.name)?;
+            match outcome.statistic {
+                Statistic::FailedBlocks(failed) => {
+                    writeln!(f, "{failed} of {} blocks failed", self.blocks)?;
+                }
+                Statistic::ChiSquare { value, p_value } => {
+                    writeln!(f, "chi-square {value:.2}, p-value {p_value:.4}")?;
+                }
+                Statistic::SerialCorrelation(coefficient) => {
+                    writeln!(f, "coefficient {coefficient:.6}")?;
+                }
+                Statistic::Pi(pi) => {
+                    let error = (pi - std::f64::consts::PI).abs() / std::f64::consts::PI;
+                    writeln!(f, "{pi:.6} (error {:.3}%)", 100.0 * error)?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "Entropy: {:.6} bits per byte, mean {:.4} (informational)",
+            self.entropy, self.mean
+        )?;
+        write!(
+            f,
+            "Result: {} ({} of {} tests passed)",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.outcomes.len() - self.failures(),
+            self.outcomes.len()
+        )
+    }
+}
+
+/// Runs the battery on the data.
+///
+/// A FIPS 140-2 test passes if at most 1% of the blocks fail it, as a good source fails a block
+/// now and then. Bytes after the last full block are only used by the byte tests.
+///
+/// # Errors
+///
+/// This function will return an error if the data is shorter than one FIPS 140-2 block.
+pub fn run(data: &[u8]) -> Result<Report> {
+    if data.len() < FIPS_BLOCK_BYTES {
+        return Err(Error::NotEnoughData(FIPS_BLOCK_BYTES));
+    }
+
+    let blocks = data.len() / FIPS_BLOCK_BYTES;
+    let mut failed = [0usize; 4];
+    for block in data.chunks_exact(FIPS_BLOCK_BYTES) {
+        let results = [monobit(block), poker(block), runs(block), long_run(block)];
+        for (failed, passed) in failed.iter_mut().zip(results) {
+            *failed += usize::from(!passed);
+        }
+    }
+
+    let mut outcomes: Vec<Outcome> = ["monobit", "poker", "runs", "long run"]
+        .into_iter()
+        .zip(failed)
+        .map(|(name, failed)| Outcome {
+            name,
+            statistic: Statistic::FailedBlocks(failed),
+            passed: failed * 100 <= blocks,
+        })
+        .collect();
+
+    let counts = byte_counts(data);
+    let n = data.len() as f64;
+
+    let (value, p_value) = chi_square(&counts, n);
+    outcomes.push(Outcome {
+        name: "chi-square",
+        statistic: Statistic::ChiSquare { value, p_value },
+        passed: (CHI_SQUARE_ALPHA..=1.0 - CHI_SQUARE_ALPHA).contains(&p_value),
+    });
+
+    let coefficient = serial_correlation(data);
+    outcomes.push(Outcome {
+        name: "serial correlation",
+        statistic: Statistic::SerialCorrelation(coefficient),
+        // the coefficient of independent bytes is about normal with variance 1/n
+        passed: (coefficient * n.sqrt()).abs() < Z_CRITICAL,
+    });
+
+    let (pi, z) = monte_carlo_pi(data);
+    outcomes.push(Outcome {
+        name: "monte carlo pi",
+        statistic: Statistic::Pi(pi),
+        passed: z.abs() < Z_CRITICAL,
+    });
+
+    let entropy = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 / n)
+        .fold(0.0, |entropy, p| entropy - p * p.log2());
+    let mean = data.iter().map(|&byte| f64::from(byte)).sum::<f64>() / n;
+
+    Ok(Report {
+        bytes: data.len(),
+        blocks,
+        outcomes,
+        entropy,
+        mean,
+    })
+}
+
+/// The bits of the block, most significant bit of each byte first.
+fn bits(block: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    block
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |shift| (byte >> shift) & 1 == 1))
+}
+
+/// Lengths of the runs of equal bits in the block, with the value of the bits.
+fn run_lengths(block: &[u8]) -> Vec<(bool, u32)> {
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for bit in bits(block) {
+        match runs.last_mut() {
+            Some((value, length)) if *value == bit => *length += 1,
+            _ => runs.push((bit, 1)),
+        }
+    }
+    runs
+}
+
+/// FIPS 140-2 monobit test: the number of ones is close to half of the bits.
+fn monobit(block: &[u8]) -> bool {
+    let ones: u32 = block.iter().map(|byte| byte.count_ones()).sum();
+    MONOBIT_BOUNDS.0 < ones && ones < MONOBIT_BOUNDS.1
+}
+
+/// FIPS 140-2 poker test: the 4-bit values are evenly distributed.
+fn poker(block: &[u8]) -> bool {
+    let mut counts = [0u32; 16];
+    for &byte in block {
+        counts[usize::from(byte >> 4)] += 1;
+        counts[usize::from(byte & 0x0f)] += 1;
+    }
+    let squares: f64 = counts.iter().map(|&count| f64::from(count * count)).sum();
+    let x = 16.0 / 5000.0 * squares - 5000.0;
+    POKER_BOUNDS.0 < x && x < POKER_BOUNDS.1
+}
+
+/// FIPS 140-2 runs test: the runs of each length, of both ones and zeroes, are about as many
+/// as expected.
+fn runs(block: &[u8]) -> bool {
+    let mut counts = [[0u32; 6]; 2];
+    for (value, length) in run_lengths(block) {
+        counts[usize::from(value)][length.min(6) as usize - 1] += 1;
+    }
+    counts.iter().all(|counts| {
+        counts
+            .iter()
+            .zip(RUN_BOUNDS)
+            .all(|(count, (low, high))| (low..=high).contains(count))
+    })
+}
+
+/// FIPS 140-2 long run test: no run of [`LONG_RUN`] or more equal bits.
+fn long_run(block: &[u8]) -> bool {
+    run_lengths(block)
+        .into_iter()
+        .all(|(_, length)| length < LONG_RUN)
+}
+
+fn byte_counts(data: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for &byte in data {
+        counts[usize::from(byte)] += 1;
+    }
+    counts
+}
+
+/// Chi-square of the byte counts against the uniform distribution, and the probability of a
+/// larger value. The p-value uses the Wilson–Hilferty approximation, which is accurate to a
+/// few parts in a thousand at 255 degrees of freedom.
+fn chi_square(counts: &[u64; 256], n: f64) -> (f64, f64) {
+    let expected = n / 256.0;
+    let value: f64 = counts
+        .iter()
+        .map(|&count| {
+            let difference = count as f64 - expected;
+            difference * difference / expected
+        })
+        .sum();
+
+    let k = 255.0;
+    let variance = 2.0 / (9.0 * k);
+    let z = ((value / k).cbrt() - (1.0 - variance)) / variance.sqrt();
+    (value, normal_upper_tail(z))
+}
+
+/// Probability that a standard normal variable exceeds `z`.
+fn normal_upper_tail(z: f64) -> f64 {
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function, with an absolute error below 1.2e-7. (Numerical Recipes 6.2)
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let polynomial = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |sum, coefficient| sum * t + coefficient);
+    let erfc = t * (-x * x + polynomial).exp();
+    if x >= 0.0 {
+        erfc
+    } else {
+        2.0 - erfc
+    }
+}
+
+/// Serial correlation coefficient of each byte with the next, the last with the first as in
+/// `ent`.
+fn serial_correlation(data: &[u8]) -> f64 {
+    let n = data.len() as f64;
+    let next = data.iter().cycle().skip(1);
+    let (mut products, mut sum, mut squares) = (0.0, 0.0, 0.0);
+    for (&byte, &next) in data.iter().zip(next) {
+        let (byte, next) = (f64::from(byte), f64::from(next));
+        products += byte * next;
+        sum += byte;
+        squares += byte * byte;
+    }
+    let denominator = n * squares - sum * sum;
+    if denominator == 0.0 {
+        // constant data, as correlated as it gets
+        return 1.0;
+    }
+    (n * products - sum * sum) / denominator
+}
+
+/// Monte Carlo value of pi from 24-bit coordinates in 6 byte groups, as in `ent`, and the
+/// deviation of the hits inside the circle from the expected in standard deviations.
+fn monte_carlo_pi(data: &[u8]) -> (f64, f64) {
+    let radius = f64::from((1u32 << 24) - 1);
+    let mut inside = 0u64;
+    let mut tries = 0u64;
+    for group in data.chunks_exact(6) {
+        let coordinate =
+            |bytes: &[u8]| f64::from(bytes.iter().fold(0u32, |sum, &b| sum << 8 | u32::from(b)));
+        let (x, y) = (coordinate(&group[..3]), coordinate(&group[3..]));
+        tries += 1;
+        inside += u64::from(x * x + y * y <= radius * radius);
+    }
+
+    let tries = tries as f64;
+    let p = std::f64::consts::FRAC_PI_4;
+    let z = (inside as f64 - tries * p) / (tries * p * (1.0 - p)).sqrt();
+    (4.0 * inside as f64 / tries, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{Generator, Seeded};
+
+    fn random_bytes(count: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut bytes = vec![0; count];
+        Seeded::new(&[1; 48], true)?.try_fill_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// A block whose first `ones` bits are set.
+    fn block_with_ones(ones: usize) -> Vec<u8> {
+        let mut block = vec![0; FIPS_BLOCK_BYTES];
+        for bit in 0..ones {
+            block[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        block
+    }
+
+    /// An alternating block starting with a run of `length` ones.
+    fn block_with_run(length: usize) -> Vec<u8> {
+        let mut block = vec![0x55; FIPS_BLOCK_BYTES];
+        for bit in 0..=length {
+            let mask = 0x80 >> (bit % 8);
+            if bit < length {
+                block[bit / 8] |= mask;
+            } else {
+                block[bit / 8] &= !mask;
+            }
+        }
+        block
+    }
+
+    #[test]
+    fn test_random_passes() -> Result<(), Box<dyn std::error::Error>> {
+        let report = run(&random_bytes(500_000)?)?;
+        assert_eq!(report.blocks, 200);
+        assert!(report.passed(), "{report}");
+        assert!(report.entropy > 7.99);
+        Ok(())
+    }
+
+    #[test]
+    fn test_broken_sources_fail() -> Result<(), Box<dyn std::error::Error>> {
+        let zeroes = run(&[0; 10_000])?;
+        assert_eq!(zeroes.failures(), zeroes.outcomes.len());
+
+        // balanced, but the runs and the 4-bit values are all wrong
+        let alternating = run(&[0x55; 10_000])?;
+        let failed: Vec<&str> = alternating
+            .outcomes
+            .iter()
+            .filter(|outcome| !outcome.passed)
+            .map(|outcome| outcome.name)
+            .collect();
+        assert!(failed.contains(&"poker") && failed.contains(&"runs"));
+        assert!(!failed.contains(&"monobit") && !failed.contains(&"long run"));
+
+        // a counter has perfectly even bytes, too even for chi-square
+        let counter: Vec<u8> = (0..=255).cycle().take(256 * 100).collect();
+        let report = run(&counter)?;
+        assert!(matches!(
+            report.outcomes[4],
+            Outcome {
+                name: "chi-square",
+                passed: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            report.outcomes[5],
+            Outcome {
+                name: "serial correlation",
+                passed: false,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fips_bounds() {
+        assert!(!monobit(&block_with_ones(9725)));
+        assert!(monobit(&block_with_ones(9726)));
+        assert!(monobit(&block_with_ones(10274)));
+        assert!(!monobit(&block_with_ones(10275)));
+
+        assert!(long_run(&block_with_run(25)));
+        assert!(!long_run(&block_with_run(26)));
+    }
+
+    #[test]
+    fn test_statistics() -> Result<(), Box<dyn std::error::Error>> {
+        assert!((normal_upper_tail(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_upper_tail(1.96) - 0.025).abs() < 1e-4);
+        assert!((normal_upper_tail(-1.96) - 0.975).abs() < 1e-4);
+
+        let mut counts = [100; 256];
+        assert_eq!(chi_square(&counts, 25_600.0).0, 0.0);
+        counts[0] = 200;
+        counts[1] = 0;
+        assert!((chi_square(&counts, 25_600.0).0 - 200.0).abs() < 1e-9);
+
+        let (pi, _) = monte_carlo_pi(&random_bytes(600_000)?);
+        assert!((pi - std::f64::consts::PI).abs() < 0.02);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json() -> Result<(), Box<dyn std::error::Error>> {
+        let json = run(&[0; FIPS_BLOCK_BYTES])?.to_json();
+        assert!(json.starts_with("{\"passed\":false,\"bytes\":2500,\"blocks\":1,"));
+        assert!(json.contains("{\"name\":\"monobit\",\"passed\":false,\"failed_blocks\":1}"));
+        assert!(json.ends_with("]}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_enough_data() {
+        assert!(matches!(
+            run(&[0; FIPS_BLOCK_BYTES - 1]),
+            Err(Error::NotEnoughData(FIPS_BLOCK_BYTES))
+        ));
+    }
+}
@@ -1,12 +1,17 @@
 use std::fmt;
 
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
 pub struct BitVector {
     bits: Vec<bool>,
 }
 
 impl BitVector {
-    pub const fn new() -> Self {
-        Self { bits: Vec::new() }
+    pub fn new() -> Self {
+        // allocated once, so that no unwiped copies are left behind when growing
+        Self {
+            bits: Vec::with_capacity(64),
+        }
     }
 
     pub fn add_bit(&mut self, bit: bool) {
@@ -28,6 +33,14 @@ impl BitVector {
     }
 }
 
+impl Drop for BitVector {
+    fn drop(&mut self) {
+        self.bits.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for BitVector {}
+
 impl fmt::Debug for BitVector {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "BitVector {{ bits: {:?} }}", self.bits)
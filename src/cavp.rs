@@ -0,0 +1,126 @@
+//! Parser for NIST CAVP response (`.rsp`) files used by the known-answer tests.
+
+use crate::drbg::Drbg;
+
+/// A single `COUNT = n` test case from a response file.
+pub struct TestCase {
+    /// The bracketed section headers preceding the case, e.g. `("SHA-256", "")` or
+    /// `("PredictionResistance", "True")`.
+    section: Vec<(String, String)>,
+    /// The hex encoded `KEY = value` lines of the case, in file order.
+    fields: Vec<(String, Vec<u8>)>,
+}
+
+impl TestCase {
+    /// Returns true if the section has the bare header `[name]`.
+    pub fn in_section(&self, name: &str) -> bool {
+        self.section.iter().any(|(key, _)| key == name)
+    }
+
+    /// Returns the value of the section header `[name = value]`.
+    pub fn section_value(&self, name: &str) -> Option<&str> {
+        self.section
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the first field called `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.get_all(name).into_iter().next()
+    }
+
+    /// Returns every field called `name`, in file order.
+    pub fn get_all(&self, name: &str) -> Vec<&[u8]> {
+        self.fields
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_slice())
+            .collect()
+    }
+}
+
+/// Parses the test cases of a response file.
+pub fn parse(data: &str) -> Result<Vec<TestCase>, hex::FromHexError> {
+    let mut cases: Vec<TestCase> = Vec::new();
+    let mut section: Vec<(String, String)> = Vec::new();
+    let mut in_header = false;
+
+    for line in data.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if !in_header {
+                section.clear();
+                in_header = true;
+            }
+            let (key, value) = header.split_once('=').unwrap_or((header, ""));
+            section.push((key.trim().to_string(), value.trim().to_string()));
+            continue;
+        }
+        in_header = false;
+
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+        let (key, value) = (key.trim(), value.trim());
+
+        if key == "COUNT" {
+            cases.push(TestCase {
+                section: section.clone(),
+                fields: Vec::new(),
+            });
+        } else if let Some(case) = cases.last_mut() {
+            case.fields.push((key.to_string(), hex::decode(value)?));
+        }
+    }
+
+    Ok(cases)
+}
+
+/// Runs the cases of `section` in a DRBG response file and returns the number of cases.
+///
+/// The fields after the personalization string are applied in file order, so the same driver
+/// handles the no reseed, reseed and prediction resistance files of every CAVS version.
+pub fn run_drbg<D: Drbg>(data: &str, section: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut cases = 0;
+
+    for case in parse(data)?.iter().filter(|c| c.in_section(section)) {
+        let field = |name: &str| case.get(name).ok_or(format!("missing {name}"));
+        let prediction_resistance = case.section_value("PredictionResistance") == Some("True");
+        let expected = field("ReturnedBits")?;
+
+        let mut drbg = D::instantiate(
+            field("EntropyInput")?,
+            field("Nonce")?,
+            field("PersonalizationString")?,
+        )?;
+        let mut returned_bits = vec![0u8; expected.len()];
+        let mut pending: &[u8] = &[];
+
+        for (key, value) in case
+            .fields
+            .iter()
+            .skip_while(|(key, _)| key != "PersonalizationString")
+            .skip(1)
+        {
+            match key.as_str() {
+                // With prediction resistance the additional input goes to the reseed.
+                "AdditionalInput" if prediction_resistance => pending = value,
+                "AdditionalInput" => drbg.generate(&mut returned_bits, value)?,
+                "EntropyInputPR" => {
+                    drbg.reseed(value, pending)?;
+                    drbg.generate(&mut returned_bits, &[])?;
+                }
+                "EntropyInputReseed" => pending = value,
+                "AdditionalInputReseed" => drbg.reseed(pending, value)?,
+                _ => {}
+            }
+        }
+
+        assert_eq!(returned_bits, expected);
+        cases += 1;
+    }
+
+    Ok(cases)
+}
@@ -1,6 +1,49 @@
 use crate::numformat::NumFormat;
-use crate::random::Source;
-pub use clap::{Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+pub use clap::{CommandFactory, FromArgMatches, Parser};
+use keygen::alphabet::Builtin;
+use keygen::drbg::Mechanism;
+use keygen::external::External;
+use keygen::random::{
+    HardwarePolicy, Source, SourcePolicy, DEFAULT_JITTER_MIN_ENTROPY, DEFAULT_RESEED_BYTES,
+    DEFAULT_RESEED_REQUESTS, DEFAULT_RESEED_SECONDS,
+};
+use keygen::user_entropy::Kind;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+/// Default data size for RNG testing, in u64 words.
+const DEFAULT_SIZE: u32 = 1;
+
+/// Default data size for the test battery, in u64 words: 1,000,000 bytes or 400 FIPS 140-2 blocks.
+const DEFAULT_BATTERY_SIZE: u32 = 125_000;
+
+/// Options mixed into the combined DRBG, by argument id.
+const MIXED_INTO_COMBINED: [&str; 2] = ["user_entropy", "seed_file"];
+
+/// Options that only change how the combined source seeds and uses its DRBG, by argument id.
+const COMBINED_SETTINGS: [&str; 8] = [
+    "drbg",
+    "rdseed",
+    "hardware",
+    "sources",
+    "paranoid",
+    "reseed_requests",
+    "reseed_bytes",
+    "reseed_seconds",
+];
+
+/// Returns the command line option of an argument id.
+fn option_name(id: &str) -> String {
+    format!("--{}", id.replace('_', "-"))
+}
+
+/// Parses a hex encoded seed.
+fn parse_seed(seed: &str) -> Result<Zeroizing<Vec<u8>>, hex::FromHexError> {
+    hex::decode(seed).map(Zeroizing::new)
+}
 
 #[derive(Parser, Debug)]
 #[clap(about, author, version)]
@@ -12,7 +55,7 @@ pub struct Cli {
     /// Specify the alphabet to use for random value generation
     #[clap(short, long, default_value = "commonsafe")]
     // , possible_values
-    pub alphabet: Alphabet,
+    pub alphabet: Builtin,
 
     /// Specify the amount of bits for each random value
     #[clap(short, long, default_value_t = 256)]
@@ -26,13 +69,120 @@ pub struct Cli {
     #[clap(short, long, requires = "alphabet", default_value = "")]
     pub delimiter: String,
 
+    /// Specify the random number source for password generation
+    #[clap(long, default_value = "combined", conflicts_with = "rngtest")]
+    pub source: Source,
+
+    /// Specify the DRBG mechanism used to combine the random sources
+    #[clap(long, default_value = "hmac-sha256")]
+    pub drbg: Mechanism,
+
+    /// Use RDSEED instead of RDRAND in the combined source
+    #[clap(long)]
+    pub rdseed: bool,
+
+    /// Specify what the combined source does when the CPU does not support RDRAND or RDSEED
+    #[clap(long, default_value = "skip")]
+    pub hardware: HardwarePolicy,
+
+    /// Assumed min-entropy per raw CPU jitter sample in bits, sets the health test cutoffs
+    #[clap(long, default_value_t = DEFAULT_JITTER_MIN_ENTROPY)]
+    pub jitter_min_entropy: f64,
+
+    /// Sources that must contribute to the combined seed: all, os-plus-one or a number of sources
+    #[clap(long, default_value = "all")]
+    pub sources: SourcePolicy,
+
+    /// Seed a new DRBG from all sources for every random value instead of reseeding one DRBG
+    #[clap(long)]
+    pub paranoid: bool,
+
+    /// Reseed the combined DRBG from the sources after this many requests
+    #[clap(long, default_value_t = DEFAULT_RESEED_REQUESTS, conflicts_with = "paranoid")]
+    pub reseed_requests: u64,
+
+    /// Reseed the combined DRBG from the sources after this many bytes of output
+    #[clap(long, default_value_t = DEFAULT_RESEED_BYTES, conflicts_with = "paranoid")]
+    pub reseed_bytes: u64,
+
+    /// Reseed the combined DRBG from the sources after this many seconds
+    #[clap(long, default_value_t = DEFAULT_RESEED_SECONDS, conflicts_with = "paranoid")]
+    pub reseed_seconds: u64,
+
+    /// Mix a seed file into the combined DRBG and replace it with fresh output
+    /// [default path: $XDG_STATE_HOME/keygen/seed]
+    #[clap(long, value_name = "PATH")]
+    #[allow(clippy::option_option)]
+    pub seed_file: Option<Option<PathBuf>>,
+
+    /// External source to add to the combined seed, or to use alone as the external source:
+    /// device:PATH, file:PATH, command:COMMAND or egd:SOCKET [can be given several times]
+    #[clap(long, value_name = "SPEC")]
+    pub external: Vec<External>,
+
+    /// Read dice rolls, coin flips or text from the user and mix them into the combined DRBG
+    #[clap(long)]
+    pub user_entropy: Option<Kind>,
+
+    /// Number of sides of the dice for user entropy
+    #[clap(long, requires = "user_entropy", default_value_t = 6)]
+    pub dice_sides: u32,
+
+    /// Bits of user entropy that must be credited before generating anything
+    #[clap(long, requires = "user_entropy", default_value_t = 0)]
+    pub user_entropy_bits: u32,
+
+    /// Generate passwords deterministically from a hex seed of at least 48 bytes, insecure and
+    /// only for tests and reproducing bug reports
+    #[clap(
+        long,
+        value_name = "HEX",
+        value_parser = parse_seed,
+        conflicts_with_all = &["source", "rngtest", "entropy_estimate", "paranoid", "seed_file", "user_entropy", "external"]
+    )]
+    pub seed: Option<Zeroizing<Vec<u8>>>,
+
+    /// Allow the insecure deterministic generation of --seed
+    #[clap(long, requires = "seed")]
+    pub allow_insecure_seed: bool,
+
+    /// Serve random bytes from --source over the EGD protocol on this Unix socket
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "SOCKET",
+        conflicts_with_all = &["bits", "alphabet", "count", "rngtest", "entropy_estimate", "seed"]
+    )]
+    pub egd_server: Option<PathBuf>,
+
     /// Optional test mode for RNG testing. Will provide raw bytes to stdout.
     #[clap(short, long, conflicts_with_all = &["bits", "alphabet", "count"])]
     pub rngtest: Option<Source>,
 
     /// Specifies the generated data size in u64 words for RNG testing.
-    #[clap(short = 's', long, requires = "rngtest", conflicts_with_all = &["bits", "alphabet", "count"], default_value_t = 1)]
-    pub size: u32,
+    /// [default: 1, or 125000 with --battery]
+    #[clap(short = 's', long, requires = "rngtest", conflicts_with_all = &["bits", "alphabet", "count"])]
+    pub size: Option<u32>,
+
+    /// Run the built-in statistical test battery on the RNG test data instead of printing it
+    #[clap(long, requires = "rngtest")]
+    pub battery: bool,
+
+    /// Print the test battery report as JSON
+    #[clap(long, requires = "battery")]
+    pub json: bool,
+
+    /// Estimate the min-entropy of a random number source with the SP 800-90B estimators.
+    #[clap(long, conflicts_with_all = &["bits", "alphabet", "count", "rngtest"])]
+    pub entropy_estimate: Option<Source>,
+
+    /// Number of samples to collect for the min-entropy estimate.
+    #[clap(long, requires = "entropy_estimate", default_value_t = 1_000_000)]
+    pub samples: usize,
+
+    /// Size of each sample in bits (1, 2, 4 or 8) for the min-entropy estimate.
+    #[clap(long, requires = "entropy_estimate", default_value_t = 8)]
+    pub sample_bits: u32,
 
     /// Specifies the data format for RNG testing.
     #[clap(
@@ -45,11 +195,159 @@ pub struct Cli {
     pub format: NumFormat,
 }
 
-#[derive(ValueEnum, Clone, Debug, Copy)]
-pub enum Alphabet {
-    WordsFi,
-    Commonsafe,
-    Normal,
-    Ascii,
-    Assembly,
+impl Cli {
+    /// Data size for RNG testing, in u64 words.
+    #[must_use]
+    pub fn size(&self) -> u32 {
+        self.size.unwrap_or(if self.battery {
+            DEFAULT_BATTERY_SIZE
+        } else {
+            DEFAULT_SIZE
+        })
+    }
+
+    /// Exits with a usage error if the test battery is given less than one FIPS 140-2 block.
+    pub fn check_battery_size(&self) {
+        let minimum = keygen::battery::FIPS_BLOCK_BYTES.div_ceil(8);
+        if self.battery && (self.size() as usize) < minimum {
+            Self::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--battery needs a --size of at least {minimum} u64 words"),
+                )
+                .exit();
+        }
+    }
+
+    /// The source the random values are drawn from.
+    #[must_use]
+    pub fn selected_source(&self) -> Source {
+        self.rngtest
+            .or(self.entropy_estimate)
+            .unwrap_or(self.source)
+    }
+
+    /// Exits with a usage error if an option is given that the selected source does not use,
+    /// as it would be silently ignored. Options with a default value count only when they are
+    /// given on the command line.
+    pub fn check_combined_options(&self, matches: &ArgMatches) {
+        if let Some(message) = self.unused_option(matches) {
+            Self::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+    }
+
+    /// Returns the usage error for the first given option the selected source does not use.
+    fn unused_option(&self, matches: &ArgMatches) -> Option<String> {
+        let source = self.selected_source();
+        if source == Source::Combined {
+            return None;
+        }
+        let given = |id: &&str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if let Some(id) = MIXED_INTO_COMBINED.into_iter().find(given) {
+            return Some(format!(
+                "{} is only mixed into the combined source, not {source:?}",
+                option_name(id)
+            ));
+        }
+        if let Some(id) = COMBINED_SETTINGS.into_iter().find(given) {
+            return Some(format!(
+                "{} is only used by the combined source, not {source:?}",
+                option_name(id)
+            ));
+        }
+        if given(&"external") && source != Source::External {
+            return Some(format!(
+                "--external is only used by the combined and external sources, not {source:?}"
+            ));
+        }
+        if given(&"jitter_min_entropy")
+            && !matches!(source, Source::Cpujitter | Source::CpujitterRaw)
+        {
+            return Some(format!(
+                "--jitter-min-entropy is only used by the combined and CPU jitter sources, not {source:?}"
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        Cli::command().debug_assert();
+    }
+
+    /// Returns the usage error of the first unused option on the command line.
+    fn unused_option(args: &[&str]) -> Option<String> {
+        let matches = Cli::command().get_matches_from(args);
+        #[allow(clippy::unwrap_used)]
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        cli.unused_option(&matches)
+    }
+
+    #[test]
+    fn test_unused_option() {
+        // defaults and options of the selected source are fine
+        assert_eq!(unused_option(&["keygen", "--source", "os"]), None);
+        assert_eq!(
+            unused_option(&["keygen", "--drbg", "ctr-aes256", "--paranoid"]),
+            None
+        );
+        assert_eq!(
+            unused_option(&["keygen", "--rngtest", "combined", "--rdseed"]),
+            None
+        );
+        assert_eq!(
+            unused_option(&[
+                "keygen",
+                "--source",
+                "cpujitter",
+                "--jitter-min-entropy",
+                "0.1"
+            ]),
+            None
+        );
+
+        for args in [
+            &["keygen", "--source", "os", "--drbg", "hmac-sha256"][..],
+            &["keygen", "--rngtest", "rdrand", "--rdseed"],
+            &["keygen", "--source", "os", "--hardware", "require"],
+            &["keygen", "--entropy-estimate", "os", "--sources", "all"],
+            &["keygen", "--source", "os", "--paranoid"],
+            &["keygen", "--source", "os", "--reseed-requests", "65536"],
+            &["keygen", "--source", "os", "--reseed-bytes", "1"],
+            &["keygen", "--source", "os", "--reseed-seconds", "1"],
+        ] {
+            let message = unused_option(args);
+            assert!(
+                message.as_deref().is_some_and(|message| message
+                    .starts_with(&format!("{} is only used by the combined source", args[3]))),
+                "{args:?}: {message:?}"
+            );
+        }
+
+        assert!(unused_option(&["keygen", "--source", "os", "--seed-file"])
+            .is_some_and(|message| message.starts_with("--seed-file is only mixed")));
+        assert!(
+            unused_option(&["keygen", "--source", "os", "--jitter-min-entropy", "0.1"])
+                .is_some_and(|message| message.starts_with("--jitter-min-entropy"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_seed_conflicts_with_egd_server() {
+        let seed = "00".repeat(48);
+        let result = Cli::try_parse_from(["keygen", "--seed", &seed, "--egd-server", "egd.sock"]);
+        assert!(matches!(result, Err(e) if e.kind() == ErrorKind::ArgumentConflict));
+
+        let result = Cli::try_parse_from(["keygen", "--seed", &seed]);
+        assert!(result.is_ok());
+    }
 }
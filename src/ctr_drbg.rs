@@ -0,0 +1,275 @@
+//! Implementation of CTR deterministic random bit generator.
+//!
+//! Implements CTR_DRBG from NIST SP 800-90A Rev. 1. Chapter 10.2.1, with AES-256 and the
+//! block cipher derivation function.
+//! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90Ar1.pdf>
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::drbg::{
+    check_entropy_input, check_seed, decode_kat, Drbg, Error, Result, MAX_BYTES_PER_REQUEST,
+    MAX_RESEED_INTERVAL, SECURITY_STRENGTH,
+};
+
+const BLOCKLEN: usize = 16;
+const KEYLEN: usize = 32;
+const SEEDLEN: usize = KEYLEN + BLOCKLEN;
+
+pub struct CtrDrbg {
+    key: Aes256,
+    v: [u8; BLOCKLEN],
+    reseed_counter: u64,
+}
+
+/// Encrypts a single block in place.
+fn encrypt(key: &Aes256, block: &mut [u8; BLOCKLEN]) {
+    key.encrypt_block(GenericArray::from_mut_slice(block));
+}
+
+/// Block_Cipher_df, the block cipher derivation function. (SP 800-90A 10.3.2)
+///
+/// Derives a seed length output from the concatenation of the given slices.
+fn block_cipher_df(input: &[&[u8]]) -> [u8; SEEDLEN] {
+    let input_len: usize = input.iter().map(|data| data.len()).sum();
+
+    // S = L || N || input_string || 0x80, padded with zeros to a multiple of the block length
+    let mut s = Vec::with_capacity(8 + input_len + BLOCKLEN);
+    #[allow(clippy::cast_possible_truncation)]
+    s.extend_from_slice(&(input_len as u32).to_be_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    s.extend_from_slice(&(SEEDLEN as u32).to_be_bytes());
+    for data in input {
+        s.extend_from_slice(data);
+    }
+    s.push(0x80);
+    s.resize(s.len().div_ceil(BLOCKLEN) * BLOCKLEN, 0x00);
+
+    // K = leftmost(0x00010203...1F, keylen)
+    let mut k = [0u8; KEYLEN];
+    for (i, byte) in (0u8..).zip(k.iter_mut()) {
+        *byte = i;
+    }
+    let key = Aes256::new(&k.into());
+
+    let mut temp = [0u8; SEEDLEN];
+    for (i, chunk) in (0u32..).zip(temp.chunks_mut(BLOCKLEN)) {
+        // BCC(K, IV || S)
+        let mut iv = [0u8; BLOCKLEN];
+        iv[..4].copy_from_slice(&i.to_be_bytes());
+
+        let mut chaining_value = [0u8; BLOCKLEN];
+        for block in std::iter::once(&iv[..]).chain(s.chunks(BLOCKLEN)) {
+            for (c, b) in chaining_value.iter_mut().zip(block) {
+                *c ^= b;
+            }
+            encrypt(&key, &mut chaining_value);
+        }
+        chunk.copy_from_slice(&chaining_value);
+    }
+
+    k.copy_from_slice(&temp[..KEYLEN]);
+    let key = Aes256::new(&k.into());
+    let mut x = [0u8; BLOCKLEN];
+    x.copy_from_slice(&temp[KEYLEN..]);
+
+    let mut output = [0u8; SEEDLEN];
+    for chunk in output.chunks_mut(BLOCKLEN) {
+        encrypt(&key, &mut x);
+        chunk.copy_from_slice(&x);
+    }
+
+    s.zeroize();
+    k.zeroize();
+    temp.zeroize();
+    x.zeroize();
+
+    output
+}
+
+impl CtrDrbg {
+    /// V = (V + 1) mod 2^blocklen
+    fn increment_v(&mut self) {
+        self.v = u128::from_be_bytes(self.v).wrapping_add(1).to_be_bytes();
+    }
+
+    /// CTR_DRBG_Update Process. (SP 800-90A 10.2.1.2)
+    fn update(&mut self, provided_data: &[u8; SEEDLEN]) {
+        let mut temp = [0u8; SEEDLEN];
+        for chunk in temp.chunks_mut(BLOCKLEN) {
+            self.increment_v();
+            let mut block = self.v;
+            encrypt(&self.key, &mut block);
+            chunk.copy_from_slice(&block);
+        }
+
+        for (t, p) in temp.iter_mut().zip(provided_data) {
+            *t ^= p;
+        }
+
+        self.key = Aes256::new(GenericArray::from_slice(&temp[..KEYLEN]));
+        self.v.copy_from_slice(&temp[KEYLEN..]);
+        temp.zeroize();
+    }
+}
+
+impl Zeroize for CtrDrbg {
+    fn zeroize(&mut self) {
+        // the aes crate erases the key schedule of the replaced key when it is dropped
+        self.key = Aes256::new(&[0x00u8; KEYLEN].into());
+        self.v.zeroize();
+        self.reseed_counter.zeroize();
+    }
+}
+
+impl Drop for CtrDrbg {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for CtrDrbg {}
+
+impl Drbg for CtrDrbg {
+    /// CTR_DRBG Instantiate Process using a derivation function. (SP 800-90A 10.2.1.3.2)
+    fn instantiate(
+        entropy_input: &[u8],
+        nonce: &[u8],
+        personalization_string: &[u8],
+    ) -> Result<Self> {
+        check_seed(entropy_input, nonce, SECURITY_STRENGTH)?;
+
+        let mut seed_material = block_cipher_df(&[entropy_input, nonce, personalization_string]);
+
+        let mut drbg = Self {
+            key: Aes256::new(&[0x00u8; KEYLEN].into()),
+            v: [0x00u8; BLOCKLEN],
+            reseed_counter: 1,
+        };
+        drbg.update(&seed_material);
+        seed_material.zeroize();
+
+        Ok(drbg)
+    }
+
+    /// CTR_DRBG Reseed Process using a derivation function. (SP 800-90A 10.2.1.4.2)
+    fn reseed(&mut self, entropy_input: &[u8], additional_input: &[u8]) -> Result<()> {
+        check_entropy_input(entropy_input, SECURITY_STRENGTH)?;
+
+        let mut seed_material = block_cipher_df(&[entropy_input, additional_input]);
+        self.update(&seed_material);
+        seed_material.zeroize();
+        self.reseed_counter = 1;
+
+        Ok(())
+    }
+
+    /// CTR_DRBG Generate Process using a derivation function. (SP 800-90A 10.2.1.5.2)
+    fn generate(&mut self, output: &mut [u8], additional_input: &[u8]) -> Result<()> {
+        if output.len() > MAX_BYTES_PER_REQUEST {
+            return Err(Error::RequestTooLarge(output.len()));
+        }
+
+        if self.reseed_counter > MAX_RESEED_INTERVAL {
+            return Err(Error::ReseedIntervalReached);
+        }
+
+        let mut additional_input = if additional_input.is_empty() {
+            [0u8; SEEDLEN]
+        } else {
+            let additional_input = block_cipher_df(&[additional_input]);
+            self.update(&additional_input);
+            additional_input
+        };
+
+        for chunk in output.chunks_mut(BLOCKLEN) {
+            self.increment_v();
+            let mut block = self.v;
+            encrypt(&self.key, &mut block);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            block.zeroize();
+        }
+
+        self.update(&additional_input);
+        additional_input.zeroize();
+        self.reseed_counter += 1;
+
+        Ok(())
+    }
+
+    /// Runs CAVS CTR_DRBG AES-256 use df PR False (256, 256) COUNT = 0, which exercises the
+    /// instantiate, reseed and generate functions with personalization string and additional
+    /// input.
+    fn self_test() -> Result<()> {
+        let mut drbg = Self::instantiate(
+            &decode_kat("a53e371017439193591e475087aaddd5c1c386cdca0ddb68e002d80fdc401a47")?,
+            &decode_kat("a94da55afdc50ce51c9a3b8a4c448440")?,
+            &decode_kat("8b52a24a93c34ea71e1ca705eb829ba65de4d4e07fa3d86b37845ff1c7d5f6d2")?,
+        )?;
+
+        let mut returned_bits = [0u8; 16];
+        drbg.generate(
+            &mut returned_bits,
+            &decode_kat("20f422edf85ca16a01cfbe5f8d6c947fae12a857db2aa9bfc7b36581808d0d46")?,
+        )?;
+        drbg.reseed(
+            &decode_kat("dd40e5987b2716731568d276bf0c6715757903d3dede914642ddd467c879c81e")?,
+            &decode_kat("7fd81fbd2ab51c115d834e99f65ca54020ed388ed59ee07593fe125e5d73fb75")?,
+        )?;
+        drbg.generate(
+            &mut returned_bits,
+            &decode_kat("cd2cff14693e4c9efdfe260de986004930bab1c65057772a62392c3b74ebc90d")?,
+        )?;
+
+        if returned_bits[..] == decode_kat("4f78beb94d978ce9d097feadfafd355e")?[..] {
+            Ok(())
+        } else {
+            Err(Error::SelfTestFailed)
+        }
+    }
+
+    fn security_strength(&self) -> usize {
+        SECURITY_STRENGTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cavp;
+    use std::mem::ManuallyDrop;
+
+    #[test]
+    fn test_ctr_drbg_cavp_pr_false() -> Result<(), Box<dyn std::error::Error>> {
+        let data = include_str!("../testdata/cavp/CTR_DRBG_pr_false.rsp");
+        assert_eq!(cavp::run_drbg::<CtrDrbg>(data, "AES-256 use df")?, 180);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ctr_drbg_cavp_pr_true() -> Result<(), Box<dyn std::error::Error>> {
+        let data = include_str!("../testdata/cavp/CTR_DRBG_pr_true.rsp");
+        assert_eq!(cavp::run_drbg::<CtrDrbg>(data, "AES-256 use df")?, 60);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ctr_drbg_self_test() -> Result<()> {
+        CtrDrbg::self_test()
+    }
+
+    #[test]
+    fn test_ctr_drbg_zeroize_on_drop() -> Result<()> {
+        let mut drbg = ManuallyDrop::new(CtrDrbg::instantiate(&[0xaau8; 32], &[0x55u8; 16], &[])?);
+        drbg.generate(&mut [0u8; 16], &[])?;
+        assert_ne!(drbg.v, [0x00u8; BLOCKLEN]);
+
+        // SAFETY: the DRBG is not used after the drop, only the plain bytes left in its memory
+        // are read back.
+        unsafe { ManuallyDrop::drop(&mut drbg) };
+        assert_eq!(drbg.v, [0x00u8; BLOCKLEN]);
+        assert_eq!(drbg.reseed_counter, 0);
+        Ok(())
+    }
+}
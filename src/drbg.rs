@@ -0,0 +1,261 @@
+//! Common interface of the deterministic random bit generators.
+//!
+//! HMAC_DRBG, Hash_DRBG and CTR_DRBG from NIST SP 800-90A Rev. 1 implement [`Drbg`], and
+//! [`Mechanism`] selects one of them at run time.
+//! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90Ar1.pdf>
+
+use clap::ValueEnum;
+use ring::{digest, hmac};
+
+use crate::ctr_drbg::CtrDrbg;
+use crate::hash_drbg::HashDrbg;
+use crate::hmac_drbg::HmacDrbg;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Reseed interval reached")]
+    ReseedIntervalReached,
+    #[error("Entropy input of {0} bytes is shorter than the security strength")]
+    EntropyInputTooShort(usize),
+    #[error(
+        "Entropy input and nonce of {0} bytes are shorter than 1.5 times the security strength"
+    )]
+    SeedTooShort(usize),
+    #[error("Request of {0} bytes exceeds the maximum request size")]
+    RequestTooLarge(usize),
+    #[error("Known-answer self-test failed")]
+    SelfTestFailed,
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Maximum number of generate requests between reseeds. (SP 800-90A allows up to 2^48.)
+pub(crate) const MAX_RESEED_INTERVAL: u64 = 1_000_000;
+
+/// Maximum number of bytes per generate request. (2^19 bits, SP 800-90A Tables 2 and 3.)
+pub(crate) const MAX_BYTES_PER_REQUEST: usize = 1 << 16;
+
+/// Security strength of CTR_DRBG with AES-256 in bytes.
+pub(crate) const SECURITY_STRENGTH: usize = 32;
+
+/// Deterministic random bit generator functions. (SP 800-90A 9)
+///
+/// Getting the entropy input is left to the caller, so prediction resistance is achieved by
+/// calling [`Drbg::reseed`] before [`Drbg::generate`].
+pub trait Drbg {
+    /// Instantiate Process.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entropy input is shorter than the security
+    /// strength, or if the entropy input and nonce together are shorter than 1.5 times the
+    /// security strength.
+    fn instantiate(
+        entropy_input: &[u8],
+        nonce: &[u8],
+        personalization_string: &[u8],
+    ) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Reseed Process.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entropy input is shorter than the security
+    /// strength.
+    fn reseed(&mut self, entropy_input: &[u8], additional_input: &[u8]) -> Result<()>;
+
+    /// Generate Process.
+    ///
+    /// Fills `output` with random bytes. An empty `additional_input` is treated as Null.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reseed interval is reached, in which case the
+    /// caller must [`reseed`](Self::reseed) before generating more, or if `output` exceeds the
+    /// maximum request size.
+    fn generate(&mut self, output: &mut [u8], additional_input: &[u8]) -> Result<()>;
+
+    /// Known-answer health test. (SP 800-90A 11.3)
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the output does not match the expected value.
+    fn self_test() -> Result<()>
+    where
+        Self: Sized;
+
+    /// Security strength of the instantiation in bytes.
+    fn security_strength(&self) -> usize;
+}
+
+/// Returns an error unless the entropy input reaches the security strength.
+pub(crate) const fn check_entropy_input(
+    entropy_input: &[u8],
+    security_strength: usize,
+) -> Result<()> {
+    if entropy_input.len() < security_strength {
+        return Err(Error::EntropyInputTooShort(entropy_input.len()));
+    }
+    Ok(())
+}
+
+/// Returns an error unless the instantiation seed is long enough. (SP 800-90A 8.6.7)
+///
+/// The nonce needs at least half the security strength of entropy, or the entropy input must
+/// cover it, so together they need 1.5 times the security strength.
+pub(crate) const fn check_seed(
+    entropy_input: &[u8],
+    nonce: &[u8],
+    security_strength: usize,
+) -> Result<()> {
+    if let Err(error) = check_entropy_input(entropy_input, security_strength) {
+        return Err(error);
+    }
+    let seed_len = entropy_input.len() + nonce.len();
+    if seed_len < security_strength * 3 / 2 {
+        return Err(Error::SeedTooShort(seed_len));
+    }
+    Ok(())
+}
+
+/// Decodes the hex encoded known-answer test values.
+pub(crate) fn decode_kat(hex: &str) -> Result<Vec<u8>> {
+    hex::decode(hex).map_err(|_| Error::SelfTestFailed)
+}
+
+/// Approved hash functions for the hash based mechanisms. (SP 800-90A 10.1, Table 2)
+pub trait HashFunction {
+    /// Output block length in bytes.
+    const OUTLEN: usize;
+    /// Hash_DRBG seed length in bytes.
+    const SEEDLEN: usize;
+    /// Highest supported security strength in bytes.
+    const SECURITY_STRENGTH: usize;
+
+    fn digest_algorithm() -> &'static digest::Algorithm;
+    fn hmac_algorithm() -> hmac::Algorithm;
+}
+
+pub struct Sha256;
+
+impl HashFunction for Sha256 {
+    const OUTLEN: usize = 32;
+    const SEEDLEN: usize = 55;
+    const SECURITY_STRENGTH: usize = 32;
+
+    fn digest_algorithm() -> &'static digest::Algorithm {
+        &digest::SHA256
+    }
+
+    fn hmac_algorithm() -> hmac::Algorithm {
+        hmac::HMAC_SHA256
+    }
+}
+
+pub struct Sha384;
+
+impl HashFunction for Sha384 {
+    const OUTLEN: usize = 48;
+    const SEEDLEN: usize = 111;
+    const SECURITY_STRENGTH: usize = 32;
+
+    fn digest_algorithm() -> &'static digest::Algorithm {
+        &digest::SHA384
+    }
+
+    fn hmac_algorithm() -> hmac::Algorithm {
+        hmac::HMAC_SHA384
+    }
+}
+
+pub struct Sha512;
+
+impl HashFunction for Sha512 {
+    const OUTLEN: usize = 64;
+    const SEEDLEN: usize = 111;
+    const SECURITY_STRENGTH: usize = 32;
+
+    fn digest_algorithm() -> &'static digest::Algorithm {
+        &digest::SHA512
+    }
+
+    fn hmac_algorithm() -> hmac::Algorithm {
+        hmac::HMAC_SHA512
+    }
+}
+
+/// The DRBG mechanisms that can be used to combine the random sources
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Mechanism {
+    #[default]
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+    HashSha256,
+    HashSha512,
+    CtrAes256,
+}
+
+impl Mechanism {
+    /// Instantiates the selected DRBG.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entropy input is shorter than the security
+    /// strength, or if the entropy input and nonce together are shorter than 1.5 times the
+    /// security strength.
+    pub fn instantiate(
+        self,
+        entropy_input: &[u8],
+        nonce: &[u8],
+        personalization_string: &[u8],
+    ) -> Result<Box<dyn Drbg + Send>> {
+        Ok(match self {
+            Self::HmacSha256 => Box::new(HmacDrbg::<Sha256>::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+            Self::HmacSha384 => Box::new(HmacDrbg::<Sha384>::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+            Self::HmacSha512 => Box::new(HmacDrbg::<Sha512>::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+            Self::HashSha256 => Box::new(HashDrbg::<Sha256>::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+            Self::HashSha512 => Box::new(HashDrbg::<Sha512>::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+            Self::CtrAes256 => Box::new(CtrDrbg::instantiate(
+                entropy_input,
+                nonce,
+                personalization_string,
+            )?),
+        })
+    }
+}
+
+/// Runs the known-answer test of every mechanism.
+///
+/// # Errors
+///
+/// This function will return an error if any of the tests fails.
+pub fn self_test() -> Result<()> {
+    HmacDrbg::<Sha256>::self_test()?;
+    HmacDrbg::<Sha384>::self_test()?;
+    HmacDrbg::<Sha512>::self_test()?;
+    HashDrbg::<Sha256>::self_test()?;
+    HashDrbg::<Sha512>::self_test()?;
+    CtrDrbg::self_test()
+}
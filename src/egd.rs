@@ -0,0 +1,270 @@
+//! Entropy Gathering Daemon protocol.
+//!
+//! EGD serves random bytes over a Unix socket with one byte commands:
+//!
+//! * `0x00` entropy level: the reply is the available entropy in bits, a big-endian u32,
+//! * `0x01 N` read without blocking: the reply is a count byte and that many bytes,
+//! * `0x02 N` read blocking: the reply is exactly N bytes,
+//! * `0x03 MSB LSB N DATA` write N bytes of entropy worth the 16-bit count of bits: no reply,
+//! * `0x04` process id: the reply is a length byte and the process id as text.
+//!
+//! [`serve`] answers the commands from any [`Generator`], and [`EgdClient`] reads from a daemon,
+//! so that it can be used as an external source.
+
+use crate::random::Generator;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("EGD I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("EGD socket {0} is already in use")]
+    AddressInUse(PathBuf),
+    #[error("Unknown EGD command {0:#04x}")]
+    UnknownCommand(u8),
+    #[error("EGD source failed: {0}")]
+    Random(#[from] crate::random::Error),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const GET_ENTROPY_LEVEL: u8 = 0x00;
+const READ_NONBLOCKING: u8 = 0x01;
+const READ_BLOCKING: u8 = 0x02;
+const WRITE_ENTROPY: u8 = 0x03;
+const REPORT_PID: u8 = 0x04;
+
+/// Entropy level reported by the server, in bits. A DRBG does not run out.
+pub const ENTROPY_LEVEL: u32 = 4096;
+
+/// Binds the server socket, removing a stale socket nobody listens on.
+///
+/// # Errors
+///
+/// This function will return an error if another daemon listens on the socket, or the socket
+/// can not be created.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::AddressInUse(path.to_path_buf()));
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Serves the clients connecting to `listener` from `generator`, each in its own thread.
+///
+/// A client sending an unknown command, or a failing generator, only ends the connection of
+/// that client.
+///
+/// # Errors
+///
+/// This function will return an error if accepting connections fails.
+pub fn serve(listener: &UnixListener, generator: &(dyn Generator + Sync)) -> Result<()> {
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            scope.spawn(move || {
+                if let Err(error) = handle(stream, generator) {
+                    eprintln!("EGD client disconnected: {error}");
+                }
+            });
+        }
+        Ok(())
+    })
+}
+
+/// Answers the commands of one client until it disconnects.
+fn handle(mut stream: UnixStream, generator: &dyn Generator) -> Result<()> {
+    let mut command = [0u8; 1];
+    loop {
+        match stream.read_exact(&mut command) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+
+        match command[0] {
+            GET_ENTROPY_LEVEL => stream.write_all(&ENTROPY_LEVEL.to_be_bytes())?,
+            READ_NONBLOCKING => {
+                let count = read_u8(&mut stream)?;
+                let mut reply = Zeroizing::new(vec![0u8; 1 + usize::from(count)]);
+                reply[0] = count;
+                generator.try_fill_bytes(&mut reply[1..])?;
+                stream.write_all(&reply)?;
+            }
+            READ_BLOCKING => {
+                let count = read_u8(&mut stream)?;
+                let mut reply = Zeroizing::new(vec![0u8; usize::from(count)]);
+                generator.try_fill_bytes(&mut reply)?;
+                stream.write_all(&reply)?;
+            }
+            WRITE_ENTROPY => {
+                // Accepted for compatibility and discarded, the generator takes no input
+                let mut header = [0u8; 3];
+                stream.read_exact(&mut header)?;
+                let mut data = Zeroizing::new(vec![0u8; usize::from(header[2])]);
+                stream.read_exact(&mut data)?;
+            }
+            REPORT_PID => {
+                let pid = std::process::id().to_string();
+                // a u32 has at most 10 digits
+                #[allow(clippy::cast_possible_truncation)]
+                stream.write_all(&[pid.len() as u8])?;
+                stream.write_all(pid.as_bytes())?;
+            }
+            unknown => return Err(Error::UnknownCommand(unknown)),
+        }
+    }
+}
+
+fn read_u8(stream: &mut UnixStream) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// A connection to an EGD daemon.
+pub struct EgdClient {
+    stream: Mutex<UnixStream>,
+}
+
+impl EgdClient {
+    /// Connects to the daemon listening on `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the connection fails.
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    #[must_use]
+    pub const fn from_stream(stream: UnixStream) -> Self {
+        Self {
+            stream: Mutex::new(stream),
+        }
+    }
+
+    /// Returns the entropy available in the daemon, in bits.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the daemon does not answer.
+    pub fn entropy_level(&self) -> io::Result<u32> {
+        #[allow(clippy::unwrap_used)]
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(&[GET_ENTROPY_LEVEL])?;
+        let mut level = [0u8; 4];
+        stream.read_exact(&mut level)?;
+        Ok(u32::from_be_bytes(level))
+    }
+
+    /// Fills `dest` with blocking reads of at most 255 bytes each.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the daemon does not answer, or closes the
+    /// connection before `dest` is full.
+    pub fn read(&self, dest: &mut [u8]) -> io::Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let mut stream = self.stream.lock().unwrap();
+        for chunk in dest.chunks_mut(usize::from(u8::MAX)) {
+            #[allow(clippy::cast_possible_truncation)]
+            stream.write_all(&[READ_BLOCKING, chunk.len() as u8])?;
+            stream.read_exact(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the process id of the daemon.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the daemon does not answer.
+    pub fn pid(&self) -> io::Result<String> {
+        #[allow(clippy::unwrap_used)]
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(&[REPORT_PID])?;
+        let mut pid = vec![0u8; usize::from(read_u8(&mut stream)?)];
+        stream.read_exact(&mut pid)?;
+        Ok(String::from_utf8_lossy(&pid).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external::External;
+    use crate::random::Seeded;
+    use crate::testutil::TempPath;
+
+    /// A client connected to a server thread serving `generator`.
+    fn connected(generator: Seeded) -> Result<EgdClient> {
+        let (server, client) = UnixStream::pair()?;
+        std::thread::spawn(move || handle(server, &generator));
+        Ok(EgdClient::from_stream(client))
+    }
+
+    #[test]
+    fn test_commands() -> Result<(), Box<dyn std::error::Error>> {
+        let seed = [3; 48];
+        let client = connected(Seeded::new(&seed, true)?)?;
+
+        assert_eq!(client.entropy_level()?, ENTROPY_LEVEL);
+        assert_eq!(client.pid()?, std::process::id().to_string());
+
+        // longer than one request, each request is one fill of the generator
+        let mut bytes = vec![0; 600];
+        client.read(&mut bytes)?;
+        let mut expected = vec![0; 600];
+        let generator = Seeded::new(&seed, true)?;
+        for chunk in expected.chunks_mut(255) {
+            generator.try_fill_bytes(chunk)?;
+        }
+        assert_eq!(bytes, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_protocol() -> Result<(), Box<dyn std::error::Error>> {
+        let (server, mut client) = UnixStream::pair()?;
+        let generator = Seeded::new(&[4; 48], true)?;
+        let server = std::thread::spawn(move || handle(server, &generator));
+
+        // written entropy is accepted without a reply
+        client.write_all(&[WRITE_ENTROPY, 0, 16, 2, 0xaa, 0xbb])?;
+        client.write_all(&[READ_NONBLOCKING, 5])?;
+        let mut reply = [0u8; 6];
+        client.read_exact(&mut reply)?;
+        assert_eq!(reply[0], 5);
+
+        client.write_all(&[0x7f])?;
+        let result = server.join().map_err(|_| "server panicked")?;
+        assert!(matches!(result, Err(Error::UnknownCommand(0x7f))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_socket() -> Result<(), Box<dyn std::error::Error>> {
+        let path = TempPath::new("egd");
+        let listener = bind(&path)?;
+        assert!(matches!(bind(&path), Err(Error::AddressInUse(_))));
+
+        // the server thread is left running until the tests end
+        std::thread::spawn(move || serve(&listener, &Seeded::new(&[5; 48], true)?));
+        let client = EgdClient::connect(&path)?;
+        let mut bytes = [0; 32];
+        client.read(&mut bytes)?;
+        assert!(bytes.iter().any(|&byte| byte != 0));
+
+        // and as an external source
+        let source = External::Egd(path.to_path_buf()).open()?;
+        assert_ne!(source.generate_u64()?, source.generate_u64()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,844 @@
+//! Min-entropy estimation for the random sources.
+//!
+//! Implements the non-IID estimators of NIST SP 800-90B Chapter 6.3 that do not use predictors:
+//! most common value, collision, Markov, compression, t-tuple and longest repeated substring,
+//! and the IID permutation test of Chapter 5.1. Samples of more than one bit are also estimated
+//! as a bit string, as described in Chapter 3.1.3.
+//! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90B.pdf>
+
+use crate::random::{self, uniform_below, Generator, SplitMix64};
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Random number generation error: {0}")]
+    Random(#[from] random::Error),
+    #[error("Sample size of {0} bits is not one of 1, 2, 4 or 8")]
+    InvalidSampleBits(u32),
+    #[error("At least {0} samples are needed for the estimate")]
+    NotEnoughSamples(usize),
+    #[error("Compression for the permutation test failed: {0}")]
+    Compression(#[from] io::Error),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Smallest number of samples accepted, enough for the compression estimate on bits.
+pub const MIN_SAMPLES: usize = 8192;
+
+/// Number of permutations of the IID permutation test. (SP 800-90B 5.1)
+pub const PERMUTATIONS: usize = 10_000;
+
+/// Number of samples the IID permutation test runs on, to keep the run time reasonable.
+pub const PERMUTATION_SAMPLES: usize = 10_000;
+
+/// Two-sided 99% confidence bound used by the estimators.
+const Z_ALPHA: f64 = 2.576;
+
+/// Occurrences the most common t-tuple needs in the t-tuple estimate. (SP 800-90B 6.3.5)
+const TUPLE_CUTOFF: usize = 35;
+
+/// Collects `count` samples of `sample_bits` bits from the generator.
+///
+/// Each generated u64 is split into samples starting from the least significant bits.
+///
+/// # Errors
+///
+/// This function will return an error if the sample size is not 1, 2, 4 or 8 bits or if the
+/// generator fails.
+pub fn collect_samples(
+    generator: &dyn Generator,
+    count: usize,
+    sample_bits: u32,
+) -> Result<Vec<u8>> {
+    if !matches!(sample_bits, 1 | 2 | 4 | 8) {
+        return Err(Error::InvalidSampleBits(sample_bits));
+    }
+
+    let mask = (1 << sample_bits) - 1;
+    let mut samples = Vec::with_capacity(count);
+
+    while samples.len() < count {
+        let value = generator.generate_u64()?;
+        for shift in (0..64).step_by(sample_bits as usize) {
+            if samples.len() == count {
+                break;
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            samples.push(((value >> shift) & mask) as u8);
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Upper bound of the 99% confidence interval for a proportion `p` observed in `n` samples.
+fn upper_bound(p: f64, n: usize) -> f64 {
+    (p + Z_ALPHA * (p * (1.0 - p) / (n as f64 - 1.0)).sqrt()).min(1.0)
+}
+
+/// Finds `p` in `[low, high]` where the decreasing function `f` reaches `target`.
+fn solve_decreasing(f: impl Fn(f64) -> f64, target: f64, low: f64, high: f64) -> f64 {
+    if f(low) <= target {
+        return low;
+    }
+    if f(high) >= target {
+        return high;
+    }
+
+    let (mut low, mut high) = (low, high);
+    for _ in 0..64 {
+        let middle = (low + high) / 2.0;
+        if f(middle) > target {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+/// Most common value estimate. (SP 800-90B 6.3.1)
+fn most_common_value(samples: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &sample in samples {
+        counts[usize::from(sample)] += 1;
+    }
+
+    let max = counts.iter().copied().max().unwrap_or(0);
+    -upper_bound(max as f64 / samples.len() as f64, samples.len()).log2()
+}
+
+/// Mean collision time of binary samples with probability `p` for the more likely value.
+fn collision_expectation(p: f64) -> f64 {
+    let q = 1.0 - p;
+    let z = 1.0 / q;
+    // F(q) = Gamma(3, z) z^-3 e^z with Gamma(3, z) = e^-z (z^2 + 2z + 2)
+    let f = (z * z + 2.0 * z + 2.0) / (z * z * z);
+    let difference = 0.5 * (1.0 / p - 1.0 / q);
+    p / (q * q) * (1.0 + difference) * f - p / q * difference
+}
+
+/// Collision estimate for binary samples. (SP 800-90B 6.3.2)
+fn collision(bits: &[u8]) -> f64 {
+    // with two possible values a collision happens within the next two or three samples
+    let mut times = Vec::new();
+    let mut index = 0;
+    while index + 2 < bits.len() {
+        let time: u8 = if bits[index] == bits[index + 1] { 2 } else { 3 };
+        times.push(f64::from(time));
+        index += usize::from(time);
+    }
+
+    let count = times.len() as f64;
+    let mean = times.iter().sum::<f64>() / count;
+    let variance = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (count - 1.0);
+    let mean_lower = mean - Z_ALPHA * variance.sqrt() / count.sqrt();
+
+    let p = solve_decreasing(collision_expectation, mean_lower, 0.5, 1.0 - 1e-9);
+    -p.log2()
+}
+
+/// Markov estimate for binary samples. (SP 800-90B 6.3.3)
+fn markov(bits: &[u8]) -> f64 {
+    let length = bits.len() as f64;
+    let ones = bits.iter().filter(|&&bit| bit != 0).count() as f64;
+    let p1 = ones / length;
+    let p0 = 1.0 - p1;
+
+    let mut transitions = [[0usize; 2]; 2];
+    for pair in bits.windows(2) {
+        transitions[usize::from(pair[0] != 0)][usize::from(pair[1] != 0)] += 1;
+    }
+    let probability = |from: usize, to: usize| {
+        let total = transitions[from][0] + transitions[from][1];
+        if total == 0 {
+            0.0
+        } else {
+            transitions[from][to] as f64 / total as f64
+        }
+    };
+    let (p00, p01) = (probability(0, 0).ln(), probability(0, 1).ln());
+    let (p10, p11) = (probability(1, 0).ln(), probability(1, 1).ln());
+    let (p0, p1) = (p0.ln(), p1.ln());
+
+    // the most likely sequences of 128 bits, as natural logarithms
+    let most_likely = [
+        p0 + 127.0 * p00,
+        p0 + 64.0 * p01 + 63.0 * p10,
+        p0 + p01 + 126.0 * p11,
+        p1 + p10 + 126.0 * p00,
+        p1 + 64.0 * p10 + 63.0 * p01,
+        p1 + 127.0 * p11,
+    ]
+    .into_iter()
+    .fold(f64::NEG_INFINITY, f64::max);
+
+    (-most_likely / std::f64::consts::LN_2 / 128.0).min(1.0)
+}
+
+/// Compression estimate for binary samples. (SP 800-90B 6.3.4)
+fn compression(bits: &[u8]) -> f64 {
+    const BLOCK_BITS: usize = 6;
+    const DICTIONARY: usize = 1000;
+    const BLOCK_VALUES: f64 = 64.0;
+
+    let blocks: Vec<usize> = bits
+        .chunks_exact(BLOCK_BITS)
+        .map(|block| {
+            block
+                .iter()
+                .fold(0, |value, &bit| value << 1 | usize::from(bit))
+        })
+        .collect();
+    let tested = (blocks.len() - DICTIONARY) as f64;
+
+    let mut last_seen = [0usize; 1 << BLOCK_BITS];
+    for (i, &block) in blocks[..DICTIONARY].iter().enumerate() {
+        last_seen[block] = i + 1;
+    }
+
+    let (mut sum, mut sum_squares) = (0.0, 0.0);
+    for (i, &block) in blocks.iter().enumerate().skip(DICTIONARY) {
+        let distance = i + 1 - last_seen[block];
+        last_seen[block] = i + 1;
+        let log = (distance as f64).log2();
+        sum += log;
+        sum_squares += log * log;
+    }
+
+    let mean = sum / tested;
+    let deviation = 0.5907 * (sum_squares / (tested - 1.0) - mean * mean).max(0.0).sqrt();
+    let mean_lower = mean - Z_ALPHA * deviation / tested.sqrt();
+
+    // G(z) = 1/v sum_t (sum_{u<t} log2(u) z^2 (1-z)^(u-1) + log2(t) z (1-z)^(t-1))
+    let g = |z: f64| {
+        let (mut total, mut inner, mut power) = (0.0, 0.0, 1.0);
+        for t in 1..=blocks.len() {
+            let log = (t as f64).log2();
+            if t > DICTIONARY {
+                total += inner + log * z * power;
+            }
+            inner += log * z * z * power;
+            power *= 1.0 - z;
+        }
+        total / tested
+    };
+    let expectation = |p: f64| g(p) + (BLOCK_VALUES - 1.0) * g((1.0 - p) / (BLOCK_VALUES - 1.0));
+
+    let p = solve_decreasing(expectation, mean_lower, 1.0 / BLOCK_VALUES, 1.0);
+    -p.log2() / BLOCK_BITS as f64
+}
+
+/// Suffix array by prefix doubling with counting sorts.
+fn suffix_array(samples: &[u8]) -> Vec<usize> {
+    let n = samples.len();
+    let mut rank: Vec<usize> = samples.iter().map(|&s| usize::from(s)).collect();
+    let mut sa: Vec<usize> = (0..n).collect();
+    sa.sort_by_key(|&i| rank[i]);
+
+    let mut second = Vec::with_capacity(n);
+    let mut next = vec![0; n];
+    let mut counts = vec![0; n.max(256) + 1];
+    let mut k = 1;
+
+    while k < n {
+        // order by the rank of the second half, suffixes without one first
+        second.clear();
+        second.extend(n - k..n);
+        second.extend(sa.iter().filter(|&&i| i >= k).map(|&i| i - k));
+
+        // stable counting sort by the rank of the first half
+        counts.iter_mut().for_each(|c| *c = 0);
+        for &i in &second {
+            counts[rank[i] + 1] += 1;
+        }
+        for r in 1..counts.len() {
+            counts[r] += counts[r - 1];
+        }
+        for &i in &second {
+            sa[counts[rank[i]]] = i;
+            counts[rank[i]] += 1;
+        }
+
+        let key = |i: usize| (rank[i], rank.get(i + k).map_or(0, |r| r + 1));
+        next[sa[0]] = 0;
+        for j in 1..n {
+            next[sa[j]] = next[sa[j - 1]] + usize::from(key(sa[j - 1]) != key(sa[j]));
+        }
+        std::mem::swap(&mut rank, &mut next);
+
+        if rank[sa[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+
+    sa
+}
+
+/// Longest common prefixes of neighbouring suffixes, `lcp[i]` between `sa[i - 1]` and `sa[i]`.
+fn lcp_array(samples: &[u8], sa: &[usize]) -> Vec<usize> {
+    let n = samples.len();
+    let mut rank = vec![0; n];
+    for (i, &suffix) in sa.iter().enumerate() {
+        rank[suffix] = i;
+    }
+
+    let mut lcp = vec![0; n];
+    let mut h = 0;
+    for i in 0..n {
+        if rank[i] == 0 {
+            h = 0;
+            continue;
+        }
+        let j = sa[rank[i] - 1];
+        while i + h < n && j + h < n && samples[i + h] == samples[j + h] {
+            h += 1;
+        }
+        lcp[rank[i]] = h;
+        h = h.saturating_sub(1);
+    }
+
+    lcp
+}
+
+/// Counts of repeated tuples of every length.
+struct TupleCounts {
+    /// Occurrences of the most common tuple of each length, indexed by length.
+    most_common: Vec<usize>,
+    /// Pairs of positions starting with the same tuple of each length, indexed by length.
+    pairs: Vec<u64>,
+}
+
+impl TupleCounts {
+    /// Merges neighbouring suffixes from the longest common prefix down, so that the groups
+    /// after handling length `w` are the positions sharing a tuple of length `w`.
+    fn new(samples: &[u8]) -> Self {
+        let sa = suffix_array(samples);
+        let lcp = lcp_array(samples, &sa);
+        let longest = lcp.iter().copied().max().unwrap_or(0);
+
+        let mut by_length = vec![Vec::new(); longest + 1];
+        for (i, &length) in lcp.iter().enumerate().skip(1) {
+            by_length[length].push(i);
+        }
+
+        let mut parent: Vec<usize> = (0..samples.len()).collect();
+        let mut size = vec![1usize; samples.len()];
+        let find = |parent: &mut Vec<usize>, mut i: usize| {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        };
+
+        let mut most_common = vec![1; longest + 1];
+        let mut pairs = vec![0; longest + 2];
+        let mut largest = 1;
+        for length in (1..=longest).rev() {
+            let mut exact = 0;
+            for &i in &by_length[length] {
+                let a = find(&mut parent, i - 1);
+                let b = find(&mut parent, i);
+                exact += (size[a] * size[b]) as u64;
+                parent[b] = a;
+                size[a] += size[b];
+                largest = largest.max(size[a]);
+            }
+            most_common[length] = largest;
+            pairs[length] = pairs[length + 1] + exact;
+        }
+        pairs.truncate(longest + 1);
+
+        Self { most_common, pairs }
+    }
+
+    /// Longest tuple length whose most common tuple occurs at least the cutoff number of times.
+    fn tuple_length(&self) -> usize {
+        (1..self.most_common.len())
+            .take_while(|&t| self.most_common[t] >= TUPLE_CUTOFF)
+            .last()
+            .unwrap_or(0)
+    }
+}
+
+/// t-Tuple estimate. (SP 800-90B 6.3.5)
+fn t_tuple(counts: &TupleCounts, length: usize) -> Option<f64> {
+    let p = (1..=counts.tuple_length())
+        .map(|t| {
+            let p = counts.most_common[t] as f64 / (length - t + 1) as f64;
+            p.powf(1.0 / t as f64)
+        })
+        .reduce(f64::max)?;
+
+    Some(-upper_bound(p, length).log2())
+}
+
+/// Longest repeated substring estimate. (SP 800-90B 6.3.6)
+fn longest_repeated_substring(counts: &TupleCounts, length: usize) -> Option<f64> {
+    let p = (counts.tuple_length() + 1..counts.pairs.len())
+        .map(|w| {
+            let tuples = (length - w + 1) as f64;
+            let p = counts.pairs[w] as f64 / (tuples * (tuples - 1.0) / 2.0);
+            p.powf(1.0 / w as f64)
+        })
+        .reduce(f64::max)?;
+
+    Some(-upper_bound(p, length).log2())
+}
+
+/// Min-entropy of one estimator, in bits per sample of the estimated sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub name: &'static str,
+    pub min_entropy: f64,
+}
+
+/// Runs the estimators that apply to the samples.
+///
+/// Collision, Markov and compression estimates are only defined for binary samples.
+fn estimates(samples: &[u8], binary: bool) -> Vec<Estimate> {
+    let estimate = |name, min_entropy| Estimate { name, min_entropy };
+    let mut estimates = vec![estimate("Most common value", most_common_value(samples))];
+
+    if binary {
+        estimates.push(estimate("Collision", collision(samples)));
+        estimates.push(estimate("Markov", markov(samples)));
+        estimates.push(estimate("Compression", compression(samples)));
+    }
+
+    let counts = TupleCounts::new(samples);
+    if let Some(min_entropy) = t_tuple(&counts, samples.len()) {
+        estimates.push(estimate("t-Tuple", min_entropy));
+    }
+    if let Some(min_entropy) = longest_repeated_substring(&counts, samples.len()) {
+        estimates.push(estimate("Longest repeated substring", min_entropy));
+    }
+
+    estimates
+}
+
+/// Splits samples into bits, most significant bit first. (SP 800-90B 3.1.3)
+fn to_bits(samples: &[u8], sample_bits: u32) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|&sample| (0..sample_bits).rev().map(move |bit| (sample >> bit) & 1))
+        .collect()
+}
+
+/// Length of the samples compressed with bzip2, written as comma separated decimal values.
+/// (SP 800-90B 5.1.11)
+///
+/// The text of [`PERMUTATION_SAMPLES`] samples fits in one 100 kB block, so the smallest block
+/// size gives the same length as the default one and sets up much less memory.
+fn compressed_length(samples: &[u8]) -> io::Result<usize> {
+    let text: Vec<String> = samples.iter().map(u8::to_string).collect();
+    let mut encoder = BzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(text.join(",").as_bytes())?;
+    Ok(encoder.finish()?.len())
+}
+
+/// Test statistics of the IID permutation test. (SP 800-90B 5.1.1 - 5.1.11)
+///
+/// # Errors
+///
+/// This function will return an error if the compression statistic can not be computed.
+fn permutation_statistics(samples: &[u8], binary: bool) -> Result<Vec<f64>> {
+    // binary samples are tested in bytes, as Hamming weights (conversion I) or values (II)
+    let (weights, values) = if binary {
+        let bytes: Vec<u8> = samples
+            .chunks_exact(8)
+            .map(|bits| bits.iter().fold(0, |byte, &bit| byte << 1 | bit))
+            .collect();
+        let weights: Vec<u8> = bytes.iter().map(|byte| byte.count_ones() as u8).collect();
+        (weights, bytes)
+    } else {
+        (samples.to_vec(), samples.to_vec())
+    };
+
+    let mut statistics = Vec::with_capacity(19);
+
+    // excursion
+    let mean = samples.iter().map(|&s| f64::from(s)).sum::<f64>() / samples.len() as f64;
+    let mut sum = 0.0;
+    let mut excursion = 0.0_f64;
+    for (i, &sample) in samples.iter().enumerate() {
+        sum += f64::from(sample);
+        excursion = excursion.max((sum - (i + 1) as f64 * mean).abs());
+    }
+    statistics.push(excursion);
+
+    // directional runs and number of increases and decreases
+    let directions: Vec<bool> = weights.windows(2).map(|w| w[0] <= w[1]).collect();
+    let (runs, longest) = count_runs(&directions);
+    let increases = directions.iter().filter(|&&up| up).count();
+    statistics.push(runs as f64);
+    statistics.push(longest as f64);
+    statistics.push(increases.max(directions.len() - increases) as f64);
+
+    // runs based on the median
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let median = if binary {
+        0.5
+    } else {
+        f64::from(sorted[sorted.len() / 2])
+    };
+    let above: Vec<bool> = samples.iter().map(|&s| f64::from(s) >= median).collect();
+    let (runs, longest) = count_runs(&above);
+    statistics.push(runs as f64);
+    statistics.push(longest as f64);
+
+    // average and maximum collision
+    let mut collisions = Vec::new();
+    let mut seen = [false; 256];
+    let mut start = 0;
+    for (i, &value) in values.iter().enumerate() {
+        if seen[usize::from(value)] {
+            collisions.push(i - start + 1);
+            start = i + 1;
+            seen = [false; 256];
+        } else {
+            seen[usize::from(value)] = true;
+        }
+    }
+    let collision_sum: usize = collisions.iter().sum();
+    statistics.push(collision_sum as f64 / collisions.len().max(1) as f64);
+    statistics.push(collisions.iter().copied().max().unwrap_or(0) as f64);
+
+    // periodicity and covariance
+    for lag in [1, 2, 8, 16, 32] {
+        let pairs = weights.iter().zip(weights.iter().skip(lag));
+        statistics.push(pairs.clone().filter(|(a, b)| a == b).count() as f64);
+        let covariance: u64 = pairs.map(|(&a, &b)| u64::from(a) * u64::from(b)).sum();
+        statistics.push(covariance as f64);
+    }
+
+    // compression, on the original samples
+    statistics.push(compressed_length(samples)? as f64);
+
+    Ok(statistics)
+}
+
+/// Number of runs and the longest run in a sequence of booleans.
+fn count_runs(sequence: &[bool]) -> (usize, usize) {
+    let (mut runs, mut longest, mut current) = (0, 0, 0);
+    for (i, &value) in sequence.iter().enumerate() {
+        if i > 0 && sequence[i - 1] == value {
+            current += 1;
+        } else {
+            runs += 1;
+            current = 1;
+        }
+        longest = longest.max(current);
+    }
+    (runs, longest)
+}
+
+/// IID permutation test. (SP 800-90B 5.1)
+///
+/// Returns `true` if the IID assumption is not rejected. The test stops early once no
+/// statistic can reject the assumption anymore.
+///
+/// # Errors
+///
+/// This function will return an error if the seed for the shuffles can not be generated or
+/// the compression statistic can not be computed.
+pub fn permutation_test(samples: &[u8], binary: bool, permutations: usize) -> Result<bool> {
+    let original = permutation_statistics(samples, binary)?;
+    // permuted statistic greater than, equal to and less than the original
+    let mut ranks = vec![[0usize; 3]; original.len()];
+
+    let shuffler = SplitMix64::new(random::OsRand {}.generate_u64()?);
+    let mut permuted = samples.to_vec();
+
+    let decided = |ranks: &[[usize; 3]]| ranks.iter().all(|r| r[0] + r[1] > 5 && r[1] + r[2] > 5);
+
+    for _ in 0..permutations {
+        // Fisher-Yates shuffle
+        for i in (1..permuted.len()).rev() {
+            #[allow(clippy::cast_possible_truncation)]
+            let j = uniform_below(&shuffler, i as u64 + 1)? as usize;
+            permuted.swap(i, j);
+        }
+
+        let statistics = permutation_statistics(&permuted, binary)?;
+        for ((rank, statistic), original) in ranks.iter_mut().zip(statistics).zip(&original) {
+            match statistic.partial_cmp(original) {
+                Some(std::cmp::Ordering::Greater) => rank[0] += 1,
+                Some(std::cmp::Ordering::Equal) => rank[1] += 1,
+                _ => rank[2] += 1,
+            }
+        }
+
+        if decided(&ranks) {
+            return Ok(true);
+        }
+    }
+
+    Ok(decided(&ranks))
+}
+
+/// Min-entropy estimate of a sequence of samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub sample_bits: u32,
+    pub samples: usize,
+    /// Whether the IID permutation test did not reject the IID assumption.
+    pub iid: bool,
+    /// Estimates in bits per sample.
+    pub original: Vec<Estimate>,
+    /// Estimates of the samples as a bit string in bits per bit, when samples have several bits.
+    pub bitstring: Vec<Estimate>,
+    /// Final min-entropy in bits per sample.
+    pub min_entropy: f64,
+}
+
+/// Estimates the min-entropy per sample of the samples, each `sample_bits` bits wide.
+///
+/// The IID permutation test runs on the first [`PERMUTATION_SAMPLES`] samples. If the IID
+/// assumption holds, the estimate is the most common value estimate, otherwise the lowest
+/// estimate of all estimators. (SP 800-90B 3.1.3)
+///
+/// # Errors
+///
+/// This function will return an error if there are fewer than [`MIN_SAMPLES`] samples, if the
+/// sample size is not 1, 2, 4 or 8 bits or if the permutation test fails to run.
+pub fn estimate(samples: &[u8], sample_bits: u32) -> Result<Report> {
+    if !matches!(sample_bits, 1 | 2 | 4 | 8) {
+        return Err(Error::InvalidSampleBits(sample_bits));
+    }
+    if samples.len() < MIN_SAMPLES {
+        return Err(Error::NotEnoughSamples(MIN_SAMPLES));
+    }
+
+    let binary = sample_bits == 1;
+    let tested = &samples[..samples.len().min(PERMUTATION_SAMPLES)];
+    let iid = permutation_test(tested, binary, PERMUTATIONS)?;
+
+    let original = estimates(samples, binary);
+    let bitstring = if binary {
+        Vec::new()
+    } else {
+        estimates(&to_bits(samples, sample_bits), true)
+    };
+
+    let lowest = |estimates: &[Estimate]| {
+        estimates
+            .iter()
+            .take(if iid { 1 } else { estimates.len() })
+            .map(|e| e.min_entropy)
+            .fold(f64::INFINITY, f64::min)
+    };
+    let min_entropy = lowest(&original)
+        .min(f64::from(sample_bits) * lowest(&bitstring))
+        .min(f64::from(sample_bits));
+
+    Ok(Report {
+        sample_bits,
+        samples: samples.len(),
+        iid,
+        original,
+        bitstring,
+        min_entropy,
+    })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Samples: {} x {} bits", self.samples, self.sample_bits)?;
+        writeln!(
+            f,
+            "IID permutation test: {}",
+            if self.iid { "passed" } else { "rejected" }
+        )?;
+        for estimate in &self.original {
+            writeln!(
+                f,
+                "{}: {:.6} bits per sample",
+                estimate.name, estimate.min_entropy
+            )?;
+        }
+        for estimate in &self.bitstring {
+            writeln!(
+                f,
+                "{} (bit string): {:.6} bits per bit",
+                estimate.name, estimate.min_entropy
+            )?;
+        }
+        write!(
+            f,
+            "Min-entropy: {:.6} bits per {} bit sample",
+            self.min_entropy, self.sample_bits
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::xorshift64;
+
+    /// Deterministic samples with `sample_bits` bits each.
+    fn uniform(count: usize, sample_bits: u32) -> Vec<u8> {
+        xorshift64()
+            .take(count)
+            .map(|value| {
+                #[allow(clippy::cast_possible_truncation)]
+                let sample = (value >> 32) as u8 & ((1 << sample_bits) - 1) as u8;
+                sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_suffix_array() {
+        for samples in [uniform(500, 2), vec![1; 100], b"banana".to_vec()] {
+            let mut expected: Vec<usize> = (0..samples.len()).collect();
+            expected.sort_by_key(|&i| &samples[i..]);
+            let sa = suffix_array(&samples);
+            assert_eq!(sa, expected);
+
+            let lcp = lcp_array(&samples, &sa);
+            for i in 1..sa.len() {
+                let common = samples[sa[i - 1]..]
+                    .iter()
+                    .zip(&samples[sa[i]..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                assert_eq!(lcp[i], common);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tuple_counts() {
+        let samples = b"abracadabra";
+        let counts = TupleCounts::new(samples);
+        // "a" 5 times, "ab", "br", "ra" twice, "abra" twice
+        assert_eq!(counts.most_common[1..], [5, 2, 2, 2]);
+        // C(5,2) + C(2,2) + C(2,2) for single letters a, b, r
+        assert_eq!(counts.pairs[1], 12);
+        assert_eq!(counts.pairs[4], 1);
+    }
+
+    #[test]
+    fn test_most_common_value() {
+        let samples = [0, 0, 0, 1].repeat(2500);
+        // p = 0.75 with the upper confidence bound
+        let h = most_common_value(&samples);
+        assert!((h - 0.394).abs() < 0.001, "{h}");
+    }
+
+    #[test]
+    fn test_uniform_bits() {
+        let bits = uniform(100_000, 1);
+        // collision and compression are conservative even for full entropy
+        for (name, h, lowest) in [
+            ("mcv", most_common_value(&bits), 0.98),
+            ("collision", collision(&bits), 0.8),
+            ("markov", markov(&bits), 0.98),
+            ("compression", compression(&bits), 0.7),
+        ] {
+            assert!(h > lowest, "{name} {h}");
+        }
+
+        let counts = TupleCounts::new(&bits);
+        let t_tuple = t_tuple(&counts, bits.len()).unwrap_or(0.0);
+        let lrs = longest_repeated_substring(&counts, bits.len()).unwrap_or(0.0);
+        assert!(t_tuple > 0.85, "{t_tuple}");
+        assert!(lrs > 0.85, "{lrs}");
+    }
+
+    #[test]
+    fn test_dependent_bits() {
+        // every bit repeated eight times has at most 1/8 bit of entropy per bit
+        let bits: Vec<u8> = uniform(10_000, 1)
+            .into_iter()
+            .flat_map(|bit| [bit; 8])
+            .collect();
+        assert!(most_common_value(&bits) > 0.9);
+        assert!(markov(&bits) < 0.3);
+        assert!(compression(&bits) < 0.5);
+
+        let counts = TupleCounts::new(&bits);
+        let t_tuple = t_tuple(&counts, bits.len()).unwrap_or(1.0);
+        assert!(t_tuple < 0.3, "{t_tuple}");
+    }
+
+    #[test]
+    fn test_constant_bits() {
+        let bits = vec![1; MIN_SAMPLES];
+        assert!(most_common_value(&bits) < 0.01);
+        assert!(collision(&bits) < 0.01);
+        assert!(markov(&bits) < 0.01);
+        assert!(compression(&bits) < 0.01);
+    }
+
+    #[test]
+    fn test_permutation_statistics() -> Result<()> {
+        // all 19 statistics of SP 800-90B 5.1, compression last
+        let samples = uniform(2000, 8);
+        let statistics = permutation_statistics(&samples, false)?;
+        assert_eq!(statistics.len(), 19);
+        assert_eq!(statistics[18], compressed_length(&samples)? as f64);
+
+        // a repetitive sequence compresses far better than a random one
+        let repetitive: Vec<u8> = (0..2000).map(|i| (i % 4) as u8).collect();
+        assert!(compressed_length(&repetitive)? * 4 < compressed_length(&samples)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_permutation_test() -> Result<()> {
+        assert!(permutation_test(&uniform(2000, 8), false, 1000)?);
+        assert!(permutation_test(&uniform(8000, 1), true, 1000)?);
+
+        // an increasing sequence is not IID
+        let sorted: Vec<u8> = (0..2000).map(|i| (i / 8) as u8).collect();
+        assert!(!permutation_test(&sorted, false, 1000)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate() -> Result<()> {
+        let report = estimate(&uniform(MIN_SAMPLES, 8), 8)?;
+        assert_eq!(report.bitstring.len(), 6);
+        assert!(report.min_entropy > 6.0, "{report}");
+        assert!(report.min_entropy <= 8.0);
+
+        assert!(matches!(
+            estimate(&uniform(100, 8), 8),
+            Err(Error::NotEnoughSamples(_))
+        ));
+        assert!(matches!(
+            estimate(&uniform(MIN_SAMPLES, 8), 3),
+            Err(Error::InvalidSampleBits(3))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_samples() -> Result<()> {
+        struct Fixed;
+        impl Generator for Fixed {
+            fn generate_u64(&self) -> random::Result<u64> {
+                Ok(0x0123_4567_89ab_cdef)
+            }
+        }
+
+        assert_eq!(collect_samples(&Fixed, 3, 8)?, [0xef, 0xcd, 0xab]);
+        assert_eq!(collect_samples(&Fixed, 4, 4)?, [0xf, 0xe, 0xd, 0xc]);
+        assert_eq!(collect_samples(&Fixed, 70, 1)?.len(), 70);
+        assert!(matches!(
+            collect_samples(&Fixed, 1, 16),
+            Err(Error::InvalidSampleBits(16))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! External random number sources.
+//!
+//! Random bytes read from outside the process: a character device such as `/dev/hwrng`, where
+//! the kernel exposes TPM and USB TRNGs, a file or FIFO, the standard output of a command, or an
+//! EGD daemon. A source is given as `device:PATH`, `file:PATH`, `command:COMMAND LINE` or
+//! `egd:SOCKET`. It can be used alone, or as an additional contributor to the seed of the
+//! combined source.
+
+#[cfg(unix)]
+use crate::egd::EgdClient;
+use crate::random::{self, Generator};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("External source {0}: {1}")]
+    Io(External, io::Error),
+    #[error("External source {0} ran out of data")]
+    Exhausted(External),
+    #[error("External source {0} is not a character device")]
+    NotCharacterDevice(External),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where an external source reads its bytes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum External {
+    /// A character device, such as `/dev/hwrng`
+    Device(PathBuf),
+    /// A regular file or a FIFO
+    File(PathBuf),
+    /// The standard output of a command line run by the shell
+    Command(String),
+    /// An EGD daemon listening on a Unix socket
+    #[cfg(unix)]
+    Egd(PathBuf),
+}
+
+impl fmt::Display for External {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device(path) => write!(f, "device:{}", path.display()),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Command(command) => write!(f, "command:{command}"),
+            #[cfg(unix)]
+            Self::Egd(path) => write!(f, "egd:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for External {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((_, "")) => Err(format!("External source without a path or command: {s}")),
+            Some(("device", path)) => Ok(Self::Device(path.into())),
+            Some(("file", path)) => Ok(Self::File(path.into())),
+            Some(("command", command)) => Ok(Self::Command(command.to_string())),
+            #[cfg(unix)]
+            Some(("egd", path)) => Ok(Self::Egd(path.into())),
+            _ => Err(format!(
+                "Unknown external source: {s} (expected device:PATH, file:PATH, command:COMMAND or egd:SOCKET)"
+            )),
+        }
+    }
+}
+
+impl External {
+    /// Opens the source for reading. A command is started here.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the path can not be opened, a device path is not
+    /// a character device, the command can not be started, or the EGD daemon does not accept
+    /// the connection.
+    pub fn open(&self) -> Result<ExternalSource> {
+        let io_error = |error| Error::Io(self.clone(), error);
+
+        let reader = match self {
+            Self::Device(path) => {
+                let file = File::open(path).map_err(io_error)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::FileTypeExt;
+                    let metadata = file.metadata().map_err(io_error)?;
+                    if !metadata.file_type().is_char_device() {
+                        return Err(Error::NotCharacterDevice(self.clone()));
+                    }
+                }
+                Reader::File(file)
+            }
+            Self::File(path) => Reader::File(File::open(path).map_err(io_error)?),
+            Self::Command(command) => {
+                let mut child = shell(command)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .map_err(io_error)?;
+                match child.stdout.take() {
+                    Some(stdout) => Reader::Command(child, stdout),
+                    None => return Err(Error::Exhausted(self.clone())),
+                }
+            }
+            #[cfg(unix)]
+            Self::Egd(path) => Reader::Egd(EgdClient::connect(path).map_err(io_error)?),
+        };
+
+        Ok(ExternalSource {
+            external: self.clone(),
+            reader: Mutex::new(reader),
+        })
+    }
+}
+
+/// A command line run by the shell of the platform.
+fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let (shell, flag) = ("sh", "-c");
+    #[cfg(not(unix))]
+    let (shell, flag) = ("cmd", "/C");
+
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+    shell
+}
+
+enum Reader {
+    File(File),
+    Command(Child, ChildStdout),
+    #[cfg(unix)]
+    Egd(EgdClient),
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        if let Self::Command(child, _) = self {
+            // The command may go on writing forever, it is stopped once no longer read
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// An opened external source.
+pub struct ExternalSource {
+    external: External,
+    reader: Mutex<Reader>,
+}
+
+impl ExternalSource {
+    /// Fills `dest` with the next bytes of the source.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if reading fails or the source ends before `dest` is
+    /// full.
+    pub fn read(&self, dest: &mut [u8]) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let mut reader = self.reader.lock().unwrap();
+        let result = match &mut *reader {
+            Reader::File(file) => file.read_exact(dest),
+            Reader::Command(_, stdout) => stdout.read_exact(dest),
+            #[cfg(unix)]
+            Reader::Egd(client) => client.read(dest),
+        };
+
+        result.map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Exhausted(self.external.clone()),
+            _ => Error::Io(self.external.clone(), error),
+        })
+    }
+
+    #[must_use]
+    pub const fn external(&self) -> &External {
+        &self.external
+    }
+}
+
+impl Generator for ExternalSource {
+    fn generate_u64(&self) -> random::Result<u64> {
+        let mut random_bytes = Zeroizing::new([0u8; 8]);
+        self.try_fill_bytes(random_bytes.as_mut())?;
+        Ok(u64::from_be_bytes(*random_bytes))
+    }
+
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> random::Result<()> {
+        Ok(self.read(dest)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempPath;
+
+    /// A file with the given contents, removed when the guard is dropped.
+    fn temporary_file(name: &str, contents: &[u8]) -> io::Result<TempPath> {
+        let path = TempPath::new(name);
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "device:/dev/hwrng".parse(),
+            Ok(External::Device("/dev/hwrng".into()))
+        );
+        assert_eq!(
+            "command:cat /dev/urandom".parse(),
+            Ok(External::Command("cat /dev/urandom".into()))
+        );
+        assert_eq!(
+            "file:/tmp/a:b".parse::<External>().map(|e| e.to_string()),
+            Ok("file:/tmp/a:b".into())
+        );
+        assert!("file:".parse::<External>().is_err());
+        assert!("/dev/hwrng".parse::<External>().is_err());
+        assert!("socket:/run/egd".parse::<External>().is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            "egd:/run/egd-pool".parse(),
+            Ok(External::Egd("/run/egd-pool".into()))
+        );
+    }
+
+    #[test]
+    fn test_file() -> Result<(), Box<dyn std::error::Error>> {
+        let path = temporary_file("external", &[1, 2, 3, 4, 5])?;
+        let source = External::File(path.to_path_buf()).open()?;
+
+        let mut bytes = [0; 3];
+        source.read(&mut bytes)?;
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(matches!(source.read(&mut bytes), Err(Error::Exhausted(_))));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device() -> Result<(), Box<dyn std::error::Error>> {
+        let source = External::Device("/dev/urandom".into()).open()?;
+        let mut bytes = [0; 64];
+        source.read(&mut bytes)?;
+        assert!(bytes.iter().any(|&byte| byte != 0));
+
+        let path = temporary_file("device", &[0; 8])?;
+        assert!(matches!(
+            External::Device(path.to_path_buf()).open(),
+            Err(Error::NotCharacterDevice(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command() -> Result<(), Box<dyn std::error::Error>> {
+        let source = External::Command("printf kissa".into()).open()?;
+        let mut bytes = [0; 5];
+        source.read(&mut bytes)?;
+        assert_eq!(&bytes, b"kissa");
+        assert!(matches!(source.read(&mut bytes), Err(Error::Exhausted(_))));
+
+        // an endless command is stopped when the source is dropped
+        let source = External::Command("yes".into()).open()?;
+        source.read(&mut bytes)?;
+        assert_eq!(&bytes, b"y\ny\ny");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fifo() -> Result<(), Box<dyn std::error::Error>> {
+        let path = TempPath::new("fifo");
+        let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())?;
+        // SAFETY: the path is a valid C string
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let writer_path = path.to_path_buf();
+        let writer = std::thread::spawn(move || std::fs::write(writer_path, [7; 16]));
+        let source = External::File(path.to_path_buf()).open()?;
+        assert_eq!(source.generate_u64()?, 0x0707_0707_0707_0707);
+        writer.join().map_err(|_| "writer panicked")??;
+        Ok(())
+    }
+}
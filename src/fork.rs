@@ -0,0 +1,317 @@
+//! Fork and VM clone detection.
+//!
+//! A DRBG whose state is copied into a forked child process or into a cloned virtual machine
+//! produces the same output in every copy. [`CloneDetector`] notices both cases, so that the
+//! state can be reseeded before it is used again. It watches
+//!
+//! * the process id, which changes in a forked child,
+//! * a canary page advised with `MADV_WIPEONFORK`, which the kernel zeroes in a forked child
+//!   even when the process id happens to match, and
+//! * the `NEW_VMGENID=1` uevent the Linux vmgenid driver sends when the hypervisor changes the
+//!   VM generation ID, after a snapshot is restored or a VM is cloned.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+
+/// Detects forks of the process and VM generation changes since it was created.
+pub struct CloneDetector {
+    pid: AtomicU32,
+    #[cfg(target_os = "linux")]
+    canary: Option<linux::Canary>,
+    #[cfg(target_os = "linux")]
+    uevents: Mutex<Option<linux::UeventSocket>>,
+}
+
+impl Default for CloneDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CloneDetector {
+    /// Starts watching. The canary page and the uevent socket are left out where the kernel
+    /// does not support them.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            pid: AtomicU32::new(std::process::id()),
+            #[cfg(target_os = "linux")]
+            canary: linux::Canary::new(),
+            #[cfg(target_os = "linux")]
+            uevents: Mutex::new(linux::UeventSocket::new()),
+        }
+    }
+
+    /// Returns true if the process has forked or the VM generation has changed since the
+    /// previous call, or since the detector was created.
+    pub fn changed(&self) -> bool {
+        let pid = std::process::id();
+        let forked = self.pid.swap(pid, Ordering::SeqCst) != pid;
+
+        #[cfg(target_os = "linux")]
+        {
+            // both checks run, so that the canary is always rearmed
+            let wiped = self.canary.as_ref().is_some_and(linux::Canary::wiped);
+            let forked = forked || wiped;
+
+            #[allow(clippy::unwrap_used)]
+            let mut uevents = self.uevents.lock().unwrap();
+            if forked {
+                // the child gets its own socket instead of sharing the parent's
+                *uevents = linux::UeventSocket::new();
+            }
+            let cloned = uevents
+                .as_ref()
+                .is_some_and(linux::UeventSocket::vm_generation_changed);
+
+            forked || cloned
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        forked
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// An anonymous page the kernel zeroes in forked children.
+    pub struct Canary {
+        page: *mut u8,
+        size: usize,
+    }
+
+    impl Canary {
+        pub fn new() -> Option<Self> {
+            // SAFETY: sysconf has no memory safety requirements
+            let size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+
+            // SAFETY: a new private anonymous mapping does not alias any Rust memory
+            let page = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if page == libc::MAP_FAILED {
+                return None;
+            }
+            let canary = Self {
+                page: page.cast(),
+                size,
+            };
+
+            // SAFETY: the range is the mapping created above
+            if unsafe { libc::madvise(page, size, libc::MADV_WIPEONFORK) } != 0 {
+                return None;
+            }
+            // SAFETY: the page is mapped readable and writable
+            unsafe { canary.page.write_volatile(1) };
+
+            Some(canary)
+        }
+
+        /// Returns true if the page has been wiped by a fork since the previous call.
+        pub fn wiped(&self) -> bool {
+            // SAFETY: the page is mapped readable and writable until the canary is dropped
+            unsafe {
+                let wiped = self.page.read_volatile() == 0;
+                self.page.write_volatile(1);
+                wiped
+            }
+        }
+
+        #[cfg(test)]
+        pub fn wipe(&self) {
+            // SAFETY: the page is mapped readable and writable until the canary is dropped
+            unsafe { self.page.write_volatile(0) };
+        }
+    }
+
+    // SAFETY: the canary owns its mapping like a Box owns its allocation, moving it to another
+    // thread moves that ownership
+    unsafe impl Send for Canary {}
+
+    impl Drop for Canary {
+        fn drop(&mut self) {
+            // SAFETY: the mapping was created in new and is not used after this
+            unsafe { libc::munmap(self.page.cast(), self.size) };
+        }
+    }
+
+    /// A netlink socket receiving kernel uevents.
+    pub struct UeventSocket(OwnedFd);
+
+    impl UeventSocket {
+        pub fn new() -> Option<Self> {
+            // SAFETY: socket has no memory safety requirements
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if fd < 0 {
+                return None;
+            }
+            // SAFETY: the descriptor was just created and is owned by nothing else
+            let socket = Self(unsafe { OwnedFd::from_raw_fd(fd) });
+
+            // SAFETY: sockaddr_nl is plain old data, all zeroes is a valid value
+            let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            // the multicast group of kernel uevents
+            address.nl_groups = 1;
+
+            #[allow(clippy::cast_possible_truncation)]
+            let length = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            // SAFETY: the address is a valid sockaddr_nl of the given length
+            let result = unsafe {
+                libc::bind(
+                    socket.0.as_raw_fd(),
+                    std::ptr::addr_of!(address).cast(),
+                    length,
+                )
+            };
+
+            (result == 0).then_some(socket)
+        }
+
+        /// Returns true if a VM generation change has been announced since the previous call.
+        pub fn vm_generation_changed(&self) -> bool {
+            drain(|buffer| {
+                // SAFETY: the buffer is valid for writes of its length
+                let received = unsafe {
+                    libc::recv(
+                        self.0.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        buffer.len(),
+                        libc::MSG_DONTWAIT,
+                    )
+                };
+                usize::try_from(received).map_err(|_| io::Error::last_os_error())
+            })
+        }
+    }
+
+    /// Receives uevents with `receive` until none are left, and returns true if one of them
+    /// announced a VM generation change.
+    ///
+    /// Overflowing the socket buffer may have lost the announcement, so it counts as one.
+    pub fn drain(mut receive: impl FnMut(&mut [u8]) -> io::Result<usize>) -> bool {
+        let mut buffer = [0u8; 8192];
+        let mut changed = false;
+
+        loop {
+            match receive(&mut buffer) {
+                Ok(received) => changed |= announces_vm_generation_change(&buffer[..received]),
+                Err(error) => match error.raw_os_error() {
+                    Some(libc::EINTR) => {}
+                    Some(libc::ENOBUFS) => changed = true,
+                    _ => return changed,
+                },
+            }
+        }
+    }
+
+    /// Returns true if the uevent is the `NEW_VMGENID=1` announcement of the vmgenid driver.
+    pub fn announces_vm_generation_change(uevent: &[u8]) -> bool {
+        // ACTION@DEVPATH followed by KEY=VALUE fields, separated by zero bytes
+        uevent
+            .split(|&byte| byte == 0)
+            .any(|field| field == b"NEW_VMGENID=1")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unchanged() {
+        let detector = CloneDetector::new();
+        assert!(!detector.changed());
+        assert!(!detector.changed());
+    }
+
+    #[test]
+    fn test_pid_change() {
+        let detector = CloneDetector::new();
+        detector.pid.store(0, Ordering::SeqCst);
+        assert!(detector.changed());
+        assert!(!detector.changed());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_canary_wiped() {
+        let detector = CloneDetector::new();
+        let Some(canary) = &detector.canary else {
+            // MADV_WIPEONFORK needs Linux 4.14
+            return;
+        };
+        canary.wipe();
+        assert!(detector.changed());
+        assert!(!detector.changed());
+    }
+
+    /// A uevent in the form the vmgenid driver sends after a snapshot is restored.
+    #[cfg(target_os = "linux")]
+    const VMGENID_UEVENT: &[u8] = b"change@/devices/LNXSYSTM:00/LNXSYBUS:00/QEMUVGID:00\0\
+        ACTION=change\0DEVPATH=/devices/LNXSYSTM:00/LNXSYBUS:00/QEMUVGID:00\0\
+        SUBSYSTEM=acpi\0NEW_VMGENID=1\0MODALIAS=acpi:QEMUVGID:\0SEQNUM=4711\0";
+
+    /// Another uevent, of a USB device being added.
+    #[cfg(target_os = "linux")]
+    const USB_UEVENT: &[u8] = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-2\0\
+        ACTION=add\0DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-2\0\
+        SUBSYSTEM=usb\0DEVTYPE=usb_device\0SEQNUM=4712\0";
+
+    /// Drains the given messages and errors as if received from the socket, followed by
+    /// `EAGAIN` once they run out.
+    #[cfg(target_os = "linux")]
+    fn drain_messages(messages: &[Result<&[u8], i32>]) -> bool {
+        let mut messages = messages.iter();
+        linux::drain(|buffer| match messages.next() {
+            Some(Ok(message)) => {
+                buffer[..message.len()].copy_from_slice(message);
+                Ok(message.len())
+            }
+            Some(Err(errno)) => Err(std::io::Error::from_raw_os_error(*errno)),
+            None => Err(std::io::Error::from_raw_os_error(libc::EAGAIN)),
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_vmgenid_uevent() {
+        assert!(linux::announces_vm_generation_change(VMGENID_UEVENT));
+        assert!(!linux::announces_vm_generation_change(USB_UEVENT));
+        // only a whole field counts
+        assert!(!linux::announces_vm_generation_change(
+            b"change@/x\0NEW_VMGENID=10\0"
+        ));
+
+        assert!(!drain_messages(&[]));
+        assert!(!drain_messages(&[
+            Ok(USB_UEVENT),
+            Err(libc::EINTR),
+            Ok(USB_UEVENT)
+        ]));
+        assert!(drain_messages(&[
+            Ok(USB_UEVENT),
+            Ok(VMGENID_UEVENT),
+            Ok(USB_UEVENT)
+        ]));
+        // the announcement may have been lost when the socket buffer overflowed
+        assert!(drain_messages(&[Ok(USB_UEVENT), Err(libc::ENOBUFS)]));
+    }
+}
@@ -0,0 +1,299 @@
+//! Implementation of Hash deterministic random bit generator.
+//!
+//! Implements Hash_DRBG from NIST SP 800-90A Rev. 1. Chapter 10.1.1, with SHA-256 or SHA-512.
+//! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90Ar1.pdf>
+
+use std::marker::PhantomData;
+
+use ring::digest;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::drbg::{
+    check_entropy_input, check_seed, decode_kat, Drbg, Error, HashFunction, Result,
+    MAX_BYTES_PER_REQUEST, MAX_RESEED_INTERVAL,
+};
+
+pub struct HashDrbg<H: HashFunction> {
+    v: Vec<u8>,
+    c: Vec<u8>,
+    reseed_counter: u64,
+    hash: PhantomData<H>,
+}
+
+/// Known-answer test with prediction resistance: instantiate, then reseed and generate twice.
+struct KnownAnswer {
+    entropy_input: &'static str,
+    nonce: &'static str,
+    personalization_string: &'static str,
+    additional_input: [&'static str; 2],
+    entropy_input_pr: [&'static str; 2],
+    returned_bits: &'static str,
+}
+
+/// CAVS 14.3 Hash_DRBG SHA-256 PR True (256, 256) COUNT = 14.
+const KAT_SHA256: KnownAnswer = KnownAnswer {
+    entropy_input: "066dc8ce75b28966a685163fe2a4d427fbdb616650616ba282fc332b4e6f1220",
+    nonce: "559f7c64897083ec2d7370d9f0e5071f",
+    personalization_string: "886f549aad1ac63d18cbcc6685daa2c2f79eb0894cb4aef1ac544fce57f15e11",
+    additional_input: [
+        "b7215f14ac7bafd0a91772ba22f719afbd20b311636c2b1e83e4a823353fc6ea",
+        "ced31f7e0dae5bb5c043e246b29473e2fd39512ead4569eee3e3803314aba7a3",
+    ],
+    entropy_input_pr: [
+        "ff80b7d26a05bc8a7abe53286b0eeb733b715a205bfa4ff63703deadb6ea0ef4",
+        "c73832534681ede37e03846d3c841767297d246c689241d2e775be7ec996293d",
+    ],
+    returned_bits: concat!(
+        "60c234cfafb468033bf195e578ce266e1465326a96a9e03f8b893670ef62754d",
+        "5e80d553a1f84950208b9343079f2ef856e9c570618597b5dc82a2daeaa3fd9b",
+        "2fd2a0d71bc62935ccb83da0679805a0e31efee4f0e513b08317faca935e3829",
+        "48d272db763e6df32510ff1b99fff8c60eb0dd292ebcbbc80a016ed3b00e4eab",
+    ),
+};
+
+/// Hash_DRBG SHA-512 PR True (256, 256), cross-checked against OpenSSL 3.5.
+const KAT_SHA512: KnownAnswer = KnownAnswer {
+    entropy_input: "967ac9d366f96ac4245b437e75109ee19c500403e1878918c8ee52d1f77f5a9e",
+    nonce: "e43a62c6e292ea5869190e782df094ba",
+    personalization_string: "d67b982269388f970986dc8d0d7a99b4b40f2250ae7e637f41f46c7348f17630",
+    additional_input: [
+        "cadd84423c98b8a7ca3714aad52f140be19bc01086fa2264a465857b07e35387",
+        "ba314d7a3ded5cc8665a03f13c55d6d677e13fe1c83c1c995af7ed000ad99fe0",
+    ],
+    entropy_input_pr: [
+        "12a81f219dd00e05ebea1044cb8b2fca5979b0c9432c5115bb063ebd14eb2c3b",
+        "e29486eb19eee55ddac4a9257546771d8cbb1aab6a68e8e7242cc87f3ad8eedd",
+    ],
+    returned_bits: concat!(
+        "6cb93b9f94aa29bf076ef444ded631812d18f851ce84e7c199b34bb0ff47ce3d",
+        "239a95473e12402e9ab76b0cd65443e4d35ae6f762c40ce930ae758a899159b8",
+        "e471497b6b8ef6d9262aa32a3f6ce8ab0d2d73482b3929b213d1a686262eba37",
+        "a0cc049880adfa9e586062fe0b91eab60877d5b8a9b1ef159bc1e90bc00b2cfc",
+        "f39856a447ab9fab8cb915c9d900a60363c16656fbadd69ba1e4956efed6be8a",
+        "9c33c8b26bb590bc91afd3c2f276a3160a0016b2fc53557c0f18ede926d5be13",
+        "447a4b3dba0e267ae6c2648d2558ba44af0f2d72e0c52747015b405dc6b9c088",
+        "5685e008342d37e9dde6a9157f4338a177699f48825b58e9f44c6afa6863db14",
+    ),
+};
+
+/// Adds `x` to `v` modulo 2^(8 * v.len()). Both are big-endian.
+fn add_assign(v: &mut [u8], x: &[u8]) {
+    let mut carry = 0u16;
+    let mut x = x.iter().rev();
+
+    for byte in v.iter_mut().rev() {
+        let sum = u16::from(*byte) + u16::from(*x.next().unwrap_or(&0)) + carry;
+        #[allow(clippy::cast_possible_truncation)]
+        let low = sum as u8;
+        *byte = low;
+        carry = sum >> 8;
+    }
+}
+
+impl<H: HashFunction> HashDrbg<H> {
+    /// Hash of the concatenation of the given slices.
+    fn hash(input: &[&[u8]]) -> digest::Digest {
+        let mut ctx = digest::Context::new(H::digest_algorithm());
+        for data in input {
+            ctx.update(data);
+        }
+        ctx.finish()
+    }
+
+    /// Hash_df, the hash derivation function. (SP 800-90A 10.3.1)
+    ///
+    /// Fills a seed length output from the concatenation of the given slices.
+    fn hash_df(input: &[&[u8]]) -> Vec<u8> {
+        let mut output = vec![0u8; H::SEEDLEN];
+
+        #[allow(clippy::cast_possible_truncation)]
+        let no_of_bits_to_return = (H::SEEDLEN as u32 * 8).to_be_bytes();
+
+        for (counter, chunk) in (1u8..).zip(output.chunks_mut(H::OUTLEN)) {
+            let mut ctx = digest::Context::new(H::digest_algorithm());
+            ctx.update(&[counter]);
+            ctx.update(&no_of_bits_to_return);
+            for data in input {
+                ctx.update(data);
+            }
+            chunk.copy_from_slice(&ctx.finish().as_ref()[..chunk.len()]);
+        }
+
+        output
+    }
+
+    /// Runs one prediction resistance known-answer test.
+    fn run_known_answer(kat: &KnownAnswer) -> Result<()> {
+        let mut drbg = Self::instantiate(
+            &decode_kat(kat.entropy_input)?,
+            &decode_kat(kat.nonce)?,
+            &decode_kat(kat.personalization_string)?,
+        )?;
+
+        let mut returned_bits = decode_kat(kat.returned_bits)?;
+        for (entropy_input, additional_input) in
+            kat.entropy_input_pr.iter().zip(kat.additional_input)
+        {
+            drbg.reseed(&decode_kat(entropy_input)?, &decode_kat(additional_input)?)?;
+            drbg.generate(&mut returned_bits, &[])?;
+        }
+
+        if returned_bits == decode_kat(kat.returned_bits)? {
+            Ok(())
+        } else {
+            Err(Error::SelfTestFailed)
+        }
+    }
+}
+
+impl<H: HashFunction> Zeroize for HashDrbg<H> {
+    fn zeroize(&mut self) {
+        self.v.as_mut_slice().zeroize();
+        self.c.as_mut_slice().zeroize();
+        self.reseed_counter.zeroize();
+    }
+}
+
+impl<H: HashFunction> Drop for HashDrbg<H> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<H: HashFunction> ZeroizeOnDrop for HashDrbg<H> {}
+
+impl<H: HashFunction> Drbg for HashDrbg<H> {
+    /// Hash_DRBG Instantiate Process. (SP 800-90A 10.1.1.2)
+    fn instantiate(
+        entropy_input: &[u8],
+        nonce: &[u8],
+        personalization_string: &[u8],
+    ) -> Result<Self> {
+        check_seed(entropy_input, nonce, H::SECURITY_STRENGTH)?;
+
+        let v = Self::hash_df(&[entropy_input, nonce, personalization_string]);
+        let c = Self::hash_df(&[&[0x00], &v]);
+
+        Ok(Self {
+            v,
+            c,
+            reseed_counter: 1,
+            hash: PhantomData,
+        })
+    }
+
+    /// Hash_DRBG Reseed Process. (SP 800-90A 10.1.1.3)
+    fn reseed(&mut self, entropy_input: &[u8], additional_input: &[u8]) -> Result<()> {
+        check_entropy_input(entropy_input, H::SECURITY_STRENGTH)?;
+
+        let v = Self::hash_df(&[&[0x01], &self.v, entropy_input, additional_input]);
+        self.v.zeroize();
+        self.c.zeroize();
+        self.c = Self::hash_df(&[&[0x00], &v]);
+        self.v = v;
+        self.reseed_counter = 1;
+
+        Ok(())
+    }
+
+    /// Hash_DRBG Generate Process. (SP 800-90A 10.1.1.4)
+    fn generate(&mut self, output: &mut [u8], additional_input: &[u8]) -> Result<()> {
+        if output.len() > MAX_BYTES_PER_REQUEST {
+            return Err(Error::RequestTooLarge(output.len()));
+        }
+
+        if self.reseed_counter > MAX_RESEED_INTERVAL {
+            return Err(Error::ReseedIntervalReached);
+        }
+
+        if !additional_input.is_empty() {
+            let w = Self::hash(&[&[0x02], &self.v, additional_input]);
+            add_assign(&mut self.v, w.as_ref());
+        }
+
+        // Hashgen (SP 800-90A 10.1.1.4)
+        let mut data = Zeroizing::new(self.v.clone());
+        for chunk in output.chunks_mut(H::OUTLEN) {
+            chunk.copy_from_slice(&Self::hash(&[&data]).as_ref()[..chunk.len()]);
+            add_assign(&mut data, &[0x01]);
+        }
+
+        let h = Self::hash(&[&[0x03], &self.v]);
+        add_assign(&mut self.v, h.as_ref());
+        add_assign(&mut self.v, &self.c);
+        add_assign(&mut self.v, &self.reseed_counter.to_be_bytes());
+        self.reseed_counter += 1;
+
+        Ok(())
+    }
+
+    fn self_test() -> Result<()> {
+        match H::OUTLEN {
+            32 => Self::run_known_answer(&KAT_SHA256),
+            64 => Self::run_known_answer(&KAT_SHA512),
+            _ => Err(Error::SelfTestFailed),
+        }
+    }
+
+    fn security_strength(&self) -> usize {
+        H::SECURITY_STRENGTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cavp;
+    use crate::drbg::{Sha256, Sha512};
+
+    #[test]
+    fn test_add_assign() {
+        let mut v = [0x00, 0xff, 0xff];
+        add_assign(&mut v, &[0x01]);
+        assert_eq!(v, [0x01, 0x00, 0x00]);
+
+        let mut v = [0xff, 0xff];
+        add_assign(&mut v, &[0x01, 0x00, 0x02]);
+        assert_eq!(v, [0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_hash_drbg_cavp_pr_true() -> Result<(), Box<dyn std::error::Error>> {
+        let data = include_str!("../testdata/cavp/Hash_DRBG_pr_true.rsp");
+        assert_eq!(cavp::run_drbg::<HashDrbg<Sha256>>(data, "SHA-256")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_drbg_openssl_pr_false() -> Result<(), Box<dyn std::error::Error>> {
+        let data = include_str!("../testdata/openssl/Hash_DRBG_pr_false.rsp");
+        assert_eq!(cavp::run_drbg::<HashDrbg<Sha256>>(data, "SHA-256")?, 20);
+        assert_eq!(cavp::run_drbg::<HashDrbg<Sha512>>(data, "SHA-512")?, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_drbg_openssl_pr_true() -> Result<(), Box<dyn std::error::Error>> {
+        let data = include_str!("../testdata/openssl/Hash_DRBG_pr_true.rsp");
+        assert_eq!(cavp::run_drbg::<HashDrbg<Sha256>>(data, "SHA-256")?, 20);
+        assert_eq!(cavp::run_drbg::<HashDrbg<Sha512>>(data, "SHA-512")?, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_drbg_self_test() -> Result<()> {
+        HashDrbg::<Sha256>::self_test()?;
+        HashDrbg::<Sha512>::self_test()
+    }
+
+    #[test]
+    fn test_hash_drbg_zeroize() -> Result<()> {
+        let mut drbg = HashDrbg::<Sha256>::instantiate(&[0xaau8; 32], &[0x55u8; 16], &[])?;
+        drbg.zeroize();
+
+        assert_eq!(drbg.v, [0x00u8; 55]);
+        assert_eq!(drbg.c, [0x00u8; 55]);
+        assert_eq!(drbg.reseed_counter, 0);
+        Ok(())
+    }
+}
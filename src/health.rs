@@ -0,0 +1,191 @@
+//! Continuous health tests for noise sources.
+//!
+//! Implements the Repetition Count Test and the Adaptive Proportion Test from NIST SP 800-90B
+//! Chapter 4.4 for binary samples. The cutoffs are derived from the assumed min-entropy per
+//! sample, with the false positive probability alpha = 2^-20 recommended by the specification.
+//! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90B.pdf>
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    #[error("Repetition Count Test failed")]
+    RepetitionCount,
+    #[error("Adaptive Proportion Test failed")]
+    AdaptiveProportion,
+    #[error("Assumed min-entropy of {0} bits per binary sample is not in (0, 1]")]
+    InvalidMinEntropy(f64),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Negative base 2 logarithm of the false positive probability alpha.
+const ALPHA_BITS: f64 = 20.0;
+
+/// Adaptive Proportion Test window size for binary noise sources. (SP 800-90B 4.4.2)
+pub const APT_WINDOW: u32 = 1024;
+
+/// Repetition Count Test cutoff, C = 1 + ceil(-log2(alpha) / H). (SP 800-90B 4.4.1)
+#[must_use]
+pub fn rct_cutoff(min_entropy: f64) -> u32 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let cutoff = 1 + (ALPHA_BITS / min_entropy).ceil() as u32;
+    cutoff
+}
+
+/// Adaptive Proportion Test cutoff, C = 1 + CRITBINOM(W, 2^-H, 1 - alpha). (SP 800-90B 4.4.2)
+///
+/// CRITBINOM is the smallest count whose binomial cumulative distribution reaches 1 - alpha.
+#[must_use]
+pub fn apt_cutoff(min_entropy: f64, window: u32) -> u32 {
+    let p = (-min_entropy).exp2();
+    let n = f64::from(window);
+    let target = 1.0 - (-ALPHA_BITS).exp2();
+
+    // ln of the binomial coefficient C(n, k), updated incrementally
+    let mut ln_choose = 0.0;
+    let mut cdf = 0.0;
+
+    for k in 0..window {
+        let k_f = f64::from(k);
+        if k > 0 {
+            ln_choose += (n - k_f + 1.0).ln() - k_f.ln();
+        }
+        cdf += (ln_choose + k_f * p.ln() + (n - k_f) * (-p).ln_1p()).exp();
+        if cdf >= target {
+            return 1 + k;
+        }
+    }
+
+    window
+}
+
+/// Repetition Count Test and Adaptive Proportion Test over a stream of binary samples.
+///
+/// Once either test fails, every following sample fails too.
+#[derive(Debug)]
+pub struct HealthTests {
+    rct_cutoff: u32,
+    apt_cutoff: u32,
+    previous: Option<bool>,
+    repetitions: u32,
+    window_first: bool,
+    window_count: u32,
+    window_samples: u32,
+    failure: Option<Error>,
+}
+
+impl HealthTests {
+    /// Creates the tests with cutoffs for the assumed min-entropy per sample, in bits.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the min-entropy is not in (0, 1].
+    pub fn new(min_entropy: f64) -> Result<Self> {
+        if !(min_entropy > 0.0 && min_entropy <= 1.0) {
+            return Err(Error::InvalidMinEntropy(min_entropy));
+        }
+
+        Ok(Self {
+            rct_cutoff: rct_cutoff(min_entropy),
+            apt_cutoff: apt_cutoff(min_entropy, APT_WINDOW),
+            previous: None,
+            repetitions: 0,
+            window_first: false,
+            window_count: 0,
+            window_samples: 0,
+            failure: None,
+        })
+    }
+
+    /// Runs both tests on the next sample.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if either test has failed.
+    pub fn process(&mut self, sample: bool) -> Result<()> {
+        if let Some(failure) = self.failure {
+            return Err(failure);
+        }
+
+        // Repetition Count Test (SP 800-90B 4.4.1)
+        if self.previous == Some(sample) {
+            self.repetitions += 1;
+            if self.repetitions >= self.rct_cutoff {
+                return Err(*self.failure.insert(Error::RepetitionCount));
+            }
+        } else {
+            self.previous = Some(sample);
+            self.repetitions = 1;
+        }
+
+        // Adaptive Proportion Test (SP 800-90B 4.4.2)
+        if self.window_samples == 0 {
+            self.window_first = sample;
+            self.window_count = 1;
+        } else if sample == self.window_first {
+            self.window_count += 1;
+            if self.window_count >= self.apt_cutoff {
+                return Err(*self.failure.insert(Error::AdaptiveProportion));
+            }
+        }
+        self.window_samples = (self.window_samples + 1) % APT_WINDOW;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::xorshift64;
+
+    #[test]
+    fn test_cutoffs() {
+        // SP 800-90B Table 2, binary samples
+        assert_eq!(rct_cutoff(1.0), 21);
+        assert_eq!(rct_cutoff(0.5), 41);
+        assert_eq!(apt_cutoff(1.0, APT_WINDOW), 589);
+        assert_eq!(apt_cutoff(0.5, APT_WINDOW), 793);
+        assert_eq!(apt_cutoff(0.2, APT_WINDOW), 941);
+    }
+
+    #[test]
+    fn test_invalid_min_entropy() {
+        for min_entropy in [0.0, -1.0, 1.5, f64::NAN] {
+            assert!(matches!(
+                HealthTests::new(min_entropy),
+                Err(Error::InvalidMinEntropy(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_repetition_count() -> Result<()> {
+        let mut tests = HealthTests::new(1.0)?;
+        for _ in 0..20 {
+            tests.process(true)?;
+        }
+        assert_eq!(tests.process(true), Err(Error::RepetitionCount));
+        // the failure is permanent
+        assert_eq!(tests.process(false), Err(Error::RepetitionCount));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_proportion() -> Result<()> {
+        // three ones for every zero passes the repetition count test but not the proportion
+        let mut tests = HealthTests::new(1.0)?;
+        let result = (0..APT_WINDOW).try_for_each(|i| tests.process(i % 4 != 3));
+        assert_eq!(result, Err(Error::AdaptiveProportion));
+
+        // with a lower assumed min-entropy the same source passes
+        let mut tests = HealthTests::new(0.2)?;
+        (0..4 * APT_WINDOW).try_for_each(|i| tests.process(i % 4 != 3))
+    }
+
+    #[test]
+    fn test_balanced_samples_pass() -> Result<()> {
+        let mut tests = HealthTests::new(1.0)?;
+        xorshift64()
+            .take(16 * APT_WINDOW as usize)
+            .try_for_each(|value| tests.process(value & 1 == 1))
+    }
+}
@@ -1,58 +1,166 @@
 //! Implementation of HMAC deterministic random bit generator.
 //!
-//! Attempts to implement HMAC DRBG from NIST SP 800-90A Rev. 1. Chapter 10.1.2.
+//! Implements HMAC_DRBG (SHA-256, SHA-384 or SHA-512) from NIST SP 800-90A Rev. 1. Chapter 10.1.2.
 //! <https://nvlpubs.nist.gov/nistpubs/SpecialPublications/NIST.SP.800-90Ar1.pdf>
 //!
-//! No reseeding support, or any guarantees that this is correct.
+//! The instantiate, reseed, generate and uninstantiate functions follow the specification.
+//! Getting the entropy input is left to the caller, so prediction resistance is achieved by
+//! calling [`Drbg::reseed`] before [`Drbg::generate`].
 
-#![allow(dead_code)]
-
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("Reseed interval reached")]
-    ReseedIntervalReached,
-}
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+use std::marker::PhantomData;
 
 use ring::hmac;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-const MAX_RESEED_INTERVAL: u32 = 1_000_000;
+use crate::drbg::{
+    check_entropy_input, check_seed, decode_kat, MAX_BYTES_PER_REQUEST, MAX_RESEED_INTERVAL,
+};
+pub use crate::drbg::{Drbg, Error, HashFunction, Result, Sha256, Sha384, Sha512};
 
-pub struct HmacDrbg {
-    v: [u8; 32],
+pub struct HmacDrbg<H: HashFunction = Sha256> {
+    v: Vec<u8>,
     key: hmac::Key,
-    reseed_counter: u32,
+    reseed_counter: u64,
+    hash: PhantomData<H>,
 }
 
-impl HmacDrbg {
-    pub fn new(seed: &[u8], personalization_string: &[u8]) -> Self {
-        let key = hmac::Key::new(hmac::HMAC_SHA256, seed);
-        let mut h = hmac::Context::with_key(&key);
-        let mut v = [0x01u8; 32];
+/// Known-answer test with a reseed: instantiate, reseed and generate twice.
+struct KnownAnswer {
+    entropy_input: &'static str,
+    nonce: &'static str,
+    personalization_string: &'static str,
+    entropy_input_reseed: &'static str,
+    additional_input_reseed: &'static str,
+    additional_input: [&'static str; 2],
+    returned_bits: &'static str,
+}
 
-        h.update(&v);
-        h.update(personalization_string);
-        h.update(seed);
-        v.copy_from_slice(h.sign().as_ref());
+/// CAVS 14.3 HMAC_DRBG SHA-256 PR False (256, 256) COUNT = 0.
+const KAT_SHA256: KnownAnswer = KnownAnswer {
+    entropy_input: "cdb0d9117cc6dbc9ef9dcb06a97579841d72dc18b2d46a1cb61e314012bdf416",
+    nonce: "d0c0d01d156016d0eb6b7e9c7c3c8da8",
+    personalization_string: "6f0fb9eab3f9ea7ab0a719bfa879bf0aaed683307fda0c6d73ce018b6e34faaa",
+    entropy_input_reseed: "8ec6f7d5a8e2e88f43986f70b86e050d07c84b931bcf18e601c5a3eee3064c82",
+    additional_input_reseed: "1ab4ca9014fa98a55938316de8ba5a68c629b0741bdd058c4d70c91cda5099b3",
+    additional_input: [
+        "16e2d0721b58d839a122852abd3bf2c942a31c84d82fca74211871880d7162ff",
+        "53686f042a7b087d5d2eca0d2a96de131f275ed7151189f7ca52deaa78b79fb2",
+    ],
+    returned_bits: concat!(
+        "dda04a2ca7b8147af1548f5d086591ca4fd951a345ce52b3cd49d47e84aa31a1",
+        "83e31fbc42a1ff1d95afec7143c8008c97bc2a9c091df0a763848391f68cb4a3",
+        "66ad89857ac725a53b303ddea767be8dc5f605b1b95f6d24c9f06be65a973a08",
+        "9320b3cc42569dcfd4b92b62a993785b0301b3fc452445656fce22664827b88f",
+    ),
+};
 
-        Self {
-            key,
-            v,
-            reseed_counter: 1,
+/// CAVS 14.3 HMAC_DRBG SHA-384 PR False (256, 256) COUNT = 0.
+const KAT_SHA384: KnownAnswer = KnownAnswer {
+    entropy_input: "c4868db5c46fde0a10008838b5be62c349209fded42fab461b01e11723c8242a",
+    nonce: "618faba54acba1e0afd4b27cbd731ed9",
+    personalization_string: "135132cf2b8a57554bdc13c68e90dc434353e4f65a4d5ca07c3e0a13c62e7265",
+    entropy_input_reseed: "d30016b5827dc2bfe4034c6654d69775fe98432b19e3da373213d939d391f54a",
+    additional_input_reseed: "a0bbd02f6aa71a06d1642ca2cc7cdc5e8857e431b176bcf1ecd20f041467bd2d",
+    additional_input: [
+        "93ee30a9e7a0e244aa91da62f2215c7233bdfc415740d2770780cbbad61b9ba2",
+        "36d922cacca00ae89db8f0c1cae5a47d2de8e61ae09357ca431c28a07907fce1",
+    ],
+    returned_bits: concat!(
+        "2aac4cebed080c68ef0dcff348506eca568180f7370c020deda1a4c9050ce94d",
+        "4db90fd827165846d6dd6cb2031eec1634b0e7f3e0e89504e34d248e23a8fb31",
+        "cd32ff39a486946b2940f54c968f96cfc508cd871c84e68458ca7dccabc6dcfb",
+        "1e9fbef9a47caae14c5239c28686e0fc0942b0c847c9d8d987970c1c5f5f06ea",
+        "a8385575dacb1e925c0ed85e13edbb9922083f9bbbb79405411ff5dfe7061568",
+        "5df1f1e49867d0b6ed69afe8ac5e76ffab6ff3d71b4dae998faf8c7d5bc6ae4d",
+    ),
+};
+
+/// CAVS 14.3 HMAC_DRBG SHA-512 PR False (256, 256) COUNT = 0.
+const KAT_SHA512: KnownAnswer = KnownAnswer {
+    entropy_input: "da740cbc36057a8e282ae717fe7dfbb245e9e5d49908a0119c5dbcf0a1f2d5ab",
+    nonce: "46561ff612217ba3ff91baa06d4b5440",
+    personalization_string: "fc227293523ecb5b1e28c87863626627d958acc558a672b148ce19e2abd2dde4",
+    entropy_input_reseed: "1d61d4d8a41c3254b92104fd555adae0569d1835bb52657ec7fbba0fe03579c5",
+    additional_input_reseed: "b9ed8e35ad018a375b61189c8d365b00507cb1b4510d21cac212356b5bbaa8b2",
+    additional_input: [
+        "b7998998eaf9e5d34e64ff7f03de765b31f407899d20535573e670c1b402c26a",
+        "2089d49d63e0c4df58879d0cb1ba998e5b3d1a7786b785e7cf13ca5ea5e33cfd",
+    ],
+    returned_bits: concat!(
+        "5b70f3e4da95264233efbab155b828d4e231b67cc92757feca407cc9615a6608",
+        "71cb07ad1a2e9a99412feda8ee34dc9c57fa08d3f8225b30d29887d20907d123",
+        "30fffd14d1697ba0756d37491b0a8814106e46c8677d49d9157109c402ad0c24",
+        "7a2f50cd5d99e538c850b906937a05dbb8888d984bc77f6ca00b0e3bc97b16d6",
+        "d25814a54aa12143afddd8b2263690565d545f4137e593bb3ca88a37b0aadf79",
+        "726b95c61906257e6dc47acd5b6b7e4b534243b13c16ad5a0a1163c0099fce43",
+        "f428cd27c3e6463cf5e9a9621f4b3d0b3d4654316f4707675df39278d5783823",
+        "049477dcce8c57fdbd576711c91301e9bd6bb0d3e72dc46d480ed8f61fd63811",
+    ),
+};
+
+impl<H: HashFunction> HmacDrbg<H> {
+    /// Instantiates the DRBG with `seed` as entropy input and no nonce.
+    ///
+    /// The seed must contain at least 1.5 times the security strength of entropy, so that it
+    /// also covers the nonce.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the seed is shorter than 1.5 times the security
+    /// strength.
+    pub fn new(seed: &[u8], personalization_string: &[u8]) -> Result<Self> {
+        Self::instantiate(seed, &[], personalization_string)
+    }
+
+    /// HMAC_DRBG Uninstantiate Process. (SP 800-90A 9.4)
+    ///
+    /// Consumes the DRBG. The internal state is erased when it is dropped.
+    pub fn uninstantiate(self) {
+        drop(self);
+    }
+
+    /// HMAC_DRBG Update Process. (SP 800-90A 10.1.2.2)
+    ///
+    /// The provided data is the concatenation of the given slices.
+    fn update(&mut self, provided_data: &[&[u8]]) {
+        let mut k = Zeroizing::new(vec![0u8; H::OUTLEN]);
+
+        for separator in [0x00u8, 0x01u8] {
+            // K = HMAC(K, V || separator || provided_data)
+            let mut h = hmac::Context::with_key(&self.key);
+            h.update(&self.v);
+            h.update(&[separator]);
+            for data in provided_data {
+                h.update(data);
+            }
+            k.copy_from_slice(h.sign().as_ref());
+            self.key = hmac::Key::new(H::hmac_algorithm(), &k[..]);
+
+            self.next_v();
+
+            if provided_data.iter().all(|data| data.is_empty()) {
+                break;
+            }
         }
     }
 
-    /// Returns a vec of random.
+    /// V = HMAC(K, V)
+    fn next_v(&mut self) {
+        let tag = hmac::sign(&self.key, &self.v);
+        self.v.copy_from_slice(tag.as_ref());
+    }
+
+    /// Returns a vec of random, erased when dropped.
     ///
     /// # Example
     ///
     /// ```rust
     /// # fn test() -> Result<(), Box<dyn std::error::Error>> {
-    /// use keygen::hmac_drbg::HmacDrbg;
-    /// let seed = [0u8; 32]; // change these to something more random
+    /// use keygen::hmac_drbg::{HmacDrbg, Sha512};
+    /// let seed = [0u8; 48]; // change these to something more random
     /// let personalization_string = [0u8; 32];
-    /// let mut drbg = HmacDrbg::new(&seed, &personalization_string);
-    /// let random_bytes: Vec<u8> = drbg.generate_bytes(80)?;
+    /// let mut drbg = HmacDrbg::<Sha512>::new(&seed, &personalization_string)?;
+    /// let random_bytes = drbg.generate_bytes(80)?;
     /// assert_eq!(random_bytes.len(), 80);
     /// # Ok(()) }
     /// ```
@@ -60,96 +168,275 @@ impl HmacDrbg {
     /// # Errors
     ///
     /// This function will return an error if reseed interval is reached.
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn generate_bytes(&mut self, requested_bytes: usize) -> Result<Vec<u8>> {
-        if self.reseed_counter > MAX_RESEED_INTERVAL {
-            return Err(Error::ReseedIntervalReached);
-        }
-
-        let mut random_bytes = Vec::new();
-        let mut output = [0u8; 32];
-
-        while random_bytes.len() < requested_bytes {
-            let mut h = hmac::Context::with_key(&self.key);
-            h.update(&self.v);
-            output.copy_from_slice(h.sign().as_ref());
-            self.v.copy_from_slice(&output);
-
-            let bytes_to_take = std::cmp::min(32, requested_bytes - random_bytes.len());
-            random_bytes.extend_from_slice(&self.v[..bytes_to_take]);
-        }
-
-        let mut h = hmac::Context::with_key(&self.key);
-        h.update(&self.v);
-        output.copy_from_slice(h.sign().as_ref());
-        self.v.copy_from_slice(&output);
-
-        self.reseed_counter += requested_bytes as u32;
-
+    pub fn generate_bytes(&mut self, requested_bytes: usize) -> Result<Zeroizing<Vec<u8>>> {
+        let mut random_bytes = Zeroizing::new(vec![0u8; requested_bytes]);
+        self.generate(&mut random_bytes, &[])?;
         Ok(random_bytes)
     }
 
-    /// Returns a slice of random.
+    /// Returns a slice of random, erased when dropped.
     ///
     /// # Example
     ///
     /// ```rust
     /// # fn test() -> Result<(), Box<dyn std::error::Error>> {
     /// use keygen::hmac_drbg::HmacDrbg;
-    /// let seed = [0u8; 32]; // change these to something more random
+    /// let seed = [0u8; 48]; // change these to something more random
     /// let personalization_string = [0u8; 32];
-    /// let mut drbg = HmacDrbg::new(&seed, &personalization_string);
-    /// let random_bytes: [u8; 80] = drbg.generate_slice()?;
+    /// let mut drbg: HmacDrbg = HmacDrbg::new(&seed, &personalization_string)?;
+    /// let random_bytes = drbg.generate_slice::<80>()?;
     /// # Ok(()) }
     /// ```
     ///
     /// # Errors
     ///
     /// This function will return an error if reseed interval is reached.
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn generate_slice<const N: usize>(&mut self) -> Result<[u8; N]> {
+    pub fn generate_slice<const N: usize>(&mut self) -> Result<Zeroizing<[u8; N]>> {
+        let mut random_bytes = Zeroizing::new([0u8; N]);
+        self.generate(random_bytes.as_mut(), &[])?;
+        Ok(random_bytes)
+    }
+
+    /// Runs one known-answer test.
+    fn run_known_answer(kat: &KnownAnswer) -> Result<()> {
+        let mut drbg = Self::instantiate(
+            &decode_kat(kat.entropy_input)?,
+            &decode_kat(kat.nonce)?,
+            &decode_kat(kat.personalization_string)?,
+        )?;
+        drbg.reseed(
+            &decode_kat(kat.entropy_input_reseed)?,
+            &decode_kat(kat.additional_input_reseed)?,
+        )?;
+
+        let mut returned_bits = decode_kat(kat.returned_bits)?;
+        for additional_input in kat.additional_input {
+            drbg.generate(&mut returned_bits, &decode_kat(additional_input)?)?;
+        }
+
+        if returned_bits == decode_kat(kat.returned_bits)? {
+            Ok(())
+        } else {
+            Err(Error::SelfTestFailed)
+        }
+    }
+}
+
+impl<H: HashFunction> Zeroize for HmacDrbg<H> {
+    fn zeroize(&mut self) {
+        self.v.as_mut_slice().zeroize();
+        // ring does not expose the key material, so the key is replaced with an all zero key
+        self.key = hmac::Key::new(H::hmac_algorithm(), &vec![0x00u8; H::OUTLEN]);
+        self.reseed_counter.zeroize();
+    }
+}
+
+impl<H: HashFunction> Drop for HmacDrbg<H> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<H: HashFunction> ZeroizeOnDrop for HmacDrbg<H> {}
+
+impl<H: HashFunction> Drbg for HmacDrbg<H> {
+    /// HMAC_DRBG Instantiate Process. (SP 800-90A 10.1.2.3)
+    fn instantiate(
+        entropy_input: &[u8],
+        nonce: &[u8],
+        personalization_string: &[u8],
+    ) -> Result<Self> {
+        check_seed(entropy_input, nonce, H::SECURITY_STRENGTH)?;
+
+        let mut drbg = Self {
+            key: hmac::Key::new(H::hmac_algorithm(), &vec![0x00u8; H::OUTLEN]),
+            v: vec![0x01u8; H::OUTLEN],
+            reseed_counter: 1,
+            hash: PhantomData,
+        };
+        drbg.update(&[entropy_input, nonce, personalization_string]);
+
+        Ok(drbg)
+    }
+
+    /// HMAC_DRBG Reseed Process. (SP 800-90A 10.1.2.4)
+    fn reseed(&mut self, entropy_input: &[u8], additional_input: &[u8]) -> Result<()> {
+        check_entropy_input(entropy_input, H::SECURITY_STRENGTH)?;
+
+        self.update(&[entropy_input, additional_input]);
+        self.reseed_counter = 1;
+
+        Ok(())
+    }
+
+    /// HMAC_DRBG Generate Process. (SP 800-90A 10.1.2.5)
+    fn generate(&mut self, output: &mut [u8], additional_input: &[u8]) -> Result<()> {
+        if output.len() > MAX_BYTES_PER_REQUEST {
+            return Err(Error::RequestTooLarge(output.len()));
+        }
+
         if self.reseed_counter > MAX_RESEED_INTERVAL {
             return Err(Error::ReseedIntervalReached);
         }
 
-        let mut random_bytes: [u8; N] = [0u8; N];
-        let mut l = 0;
-
-        while l < N {
-            let mut h = hmac::Context::with_key(&self.key);
-            h.update(&self.v);
-            self.v.copy_from_slice(h.sign().as_ref());
+        if !additional_input.is_empty() {
+            self.update(&[additional_input]);
+        }
 
-            let bytes_to_take = std::cmp::min(32, N - l);
-            random_bytes[l..l + bytes_to_take].copy_from_slice(&self.v[..bytes_to_take]);
-            l += bytes_to_take;
+        for chunk in output.chunks_mut(H::OUTLEN) {
+            self.next_v();
+            chunk.copy_from_slice(&self.v[..chunk.len()]);
         }
 
-        let mut h = hmac::Context::with_key(&self.key);
-        h.update(&self.v);
-        self.v.copy_from_slice(h.sign().as_ref());
+        self.update(&[additional_input]);
+        self.reseed_counter += 1;
 
-        self.reseed_counter += N as u32;
+        Ok(())
+    }
 
-        Ok(random_bytes)
+    /// Runs CAVS 14.3 HMAC_DRBG PR False (256, 256) COUNT = 0 for the hash function, which
+    /// exercises the instantiate, reseed and generate functions with personalization string and
+    /// additional input.
+    fn self_test() -> Result<()> {
+        match H::OUTLEN {
+            32 => Self::run_known_answer(&KAT_SHA256),
+            48 => Self::run_known_answer(&KAT_SHA384),
+            64 => Self::run_known_answer(&KAT_SHA512),
+            _ => Err(Error::SelfTestFailed),
+        }
+    }
+
+    fn security_strength(&self) -> usize {
+        H::SECURITY_STRENGTH
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cavp;
+
+    /// Runs the SHA-256, SHA-384 and SHA-512 sections of a response file.
+    fn run_cavp(data: &str) -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(cavp::run_drbg::<HmacDrbg<Sha256>>(data, "SHA-256")?, 60);
+        assert_eq!(cavp::run_drbg::<HmacDrbg<Sha384>>(data, "SHA-384")?, 60);
+        assert_eq!(cavp::run_drbg::<HmacDrbg<Sha512>>(data, "SHA-512")?, 60);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_drbg_cavp_no_reseed() -> Result<(), Box<dyn std::error::Error>> {
+        run_cavp(include_str!("../testdata/cavp/HMAC_DRBG_no_reseed.rsp"))
+    }
+
+    #[test]
+    fn test_hmac_drbg_cavp_pr_false() -> Result<(), Box<dyn std::error::Error>> {
+        run_cavp(include_str!("../testdata/cavp/HMAC_DRBG_pr_false.rsp"))
+    }
+
+    #[test]
+    fn test_hmac_drbg_cavp_pr_true() -> Result<(), Box<dyn std::error::Error>> {
+        run_cavp(include_str!("../testdata/cavp/HMAC_DRBG_pr_true.rsp"))
+    }
+
+    #[test]
+    fn test_hmac_drbg_self_test() -> Result<()> {
+        HmacDrbg::<Sha256>::self_test()?;
+        HmacDrbg::<Sha384>::self_test()?;
+        HmacDrbg::<Sha512>::self_test()
+    }
+
+    #[test]
+    fn test_hmac_drbg_security_strength() -> Result<()> {
+        assert_eq!(
+            HmacDrbg::<Sha256>::new(&[0u8; 48], &[])?.security_strength(),
+            32
+        );
+        assert_eq!(
+            HmacDrbg::<Sha384>::new(&[0u8; 48], &[])?.security_strength(),
+            32
+        );
+        assert_eq!(
+            HmacDrbg::<Sha512>::new(&[0u8; 48], &[])?.security_strength(),
+            32
+        );
+        Ok(())
+    }
 
     #[test]
     fn test_hmac_drbg_slice_bytes() -> Result<(), Box<dyn std::error::Error>> {
-        let seed = [0u8; 32];
+        let seed = [0u8; 48];
         let personalization_string = [0u8; 32];
-        let mut drbg = HmacDrbg::new(&seed, &personalization_string);
-        let random_slice: [u8; 80] = drbg.generate_slice()?;
+        let mut drbg: HmacDrbg = HmacDrbg::new(&seed, &personalization_string)?;
+        let random_slice = drbg.generate_slice::<80>()?;
 
-        let mut drbg = HmacDrbg::new(&seed, &personalization_string);
+        let mut drbg: HmacDrbg = HmacDrbg::new(&seed, &personalization_string)?;
         let random_bytes = drbg.generate_bytes(80)?;
         assert_eq!(random_bytes.len(), 80);
-        assert_eq!(random_slice.to_vec(), random_bytes);
+        assert_eq!(random_slice.to_vec(), *random_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_drbg_reseed_interval() -> Result<(), Box<dyn std::error::Error>> {
+        let mut drbg: HmacDrbg = HmacDrbg::new(&[0u8; 48], &[])?;
+        let mut output = [0u8; 8];
+
+        drbg.reseed_counter = MAX_RESEED_INTERVAL;
+        drbg.generate(&mut output, &[])?;
+        assert!(matches!(
+            drbg.generate(&mut output, &[]),
+            Err(Error::ReseedIntervalReached)
+        ));
+
+        drbg.reseed(&[1u8; 32], b"additional input")?;
+        assert_eq!(drbg.reseed_counter, 1);
+        drbg.generate(&mut output, &[])?;
+        assert_eq!(drbg.reseed_counter, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_drbg_input_limits() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(matches!(
+            HmacDrbg::<Sha256>::instantiate(&[0u8; 16], &[], &[]),
+            Err(Error::EntropyInputTooShort(16))
+        ));
+        assert!(matches!(
+            HmacDrbg::<Sha512>::instantiate(&[0u8; 32], &[0u8; 8], &[]),
+            Err(Error::SeedTooShort(40))
+        ));
+        assert!(matches!(
+            HmacDrbg::<Sha512>::new(&[0u8; 32], &[]),
+            Err(Error::SeedTooShort(32))
+        ));
+
+        let mut drbg: HmacDrbg = HmacDrbg::new(&[0u8; 48], &[])?;
+        let mut output = vec![0u8; MAX_BYTES_PER_REQUEST + 1];
+        assert!(matches!(
+            drbg.generate(&mut output, &[]),
+            Err(Error::RequestTooLarge(_))
+        ));
+        assert!(matches!(
+            drbg.reseed(&[0u8; 31], &[]),
+            Err(Error::EntropyInputTooShort(31))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_drbg_zeroize() -> Result<()> {
+        let mut drbg = HmacDrbg::<Sha512>::new(&[0xaau8; 48], &[])?;
+        drbg.generate(&mut [0u8; 16], &[])?;
+        drbg.zeroize();
+
+        let zero_key = hmac::Key::new(hmac::HMAC_SHA512, &[0x00u8; 64]);
+        assert_eq!(drbg.v, [0x00u8; 64]);
+        assert_eq!(drbg.reseed_counter, 0);
+        assert_eq!(
+            hmac::sign(&drbg.key, b"").as_ref(),
+            hmac::sign(&zero_key, b"").as_ref()
+        );
         Ok(())
     }
 }
@@ -0,0 +1,42 @@
+//! Generates random passwords and keys.
+//!
+//! The library exposes the alphabets, the random number sources and the DRBGs used by the
+//! `keygen` binary, so that the same password generation can be embedded in other programs.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! use keygen::alphabet::commonsafe::CommonSafe;
+//! use keygen::random::OsRand;
+//!
+//! let password = keygen::generate_password(&OsRand {}, &CommonSafe {}, 128, "")?;
+//! assert!(!password.is_empty());
+//! # Ok(()) }
+//! ```
+
+pub mod alphabet;
+pub mod battery;
+mod bitvector;
+#[cfg(test)]
+mod cavp;
+pub mod ctr_drbg;
+pub mod drbg;
+#[cfg(unix)]
+pub mod egd;
+pub mod estimate;
+pub mod external;
+pub mod fork;
+pub mod hash_drbg;
+pub mod health;
+pub mod hmac_drbg;
+pub mod password;
+pub mod random;
+#[cfg(feature = "rand_core")]
+pub mod rng;
+pub mod seedfile;
+#[cfg(test)]
+mod testutil;
+pub mod user_entropy;
+
+pub use password::generate_password;
@@ -1,59 +1,177 @@
 //! Generates random passwords and keys.
 
-mod hmac_drbg;
-
-mod random;
-use random::Generator;
-
-mod alphabet;
-use alphabet::Alphabet;
+use keygen::alphabet::Alphabet;
+use keygen::external::External;
+use keygen::random::{Combined, CpuJitter, Generator, ReseedPolicy, Seeded};
+use keygen::seedfile::SeedFile;
+use keygen::user_entropy::{self, Kind, UserEntropy};
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 mod numformat;
 use numformat::PrintFormattedValue;
 
 mod cli;
-use cli::Parser;
+use cli::{CommandFactory, FromArgMatches};
+
+/// Number of u64 words generated at a time for RNG testing.
+const RNGTEST_BUFFER_WORDS: usize = 4096;
+
+/// Reads the user entropy, and returns it encoded as additional input.
+fn read_user_entropy(
+    cli: &cli::Cli,
+    kind: Kind,
+) -> Result<Zeroizing<Vec<u8>>, user_entropy::Error> {
+    let mut user_entropy = UserEntropy::new(kind, cli.dice_sides)?;
+    user_entropy.read_interactive()?;
+    user_entropy.require(cli.user_entropy_bits)?;
+    eprintln!(
+        "User entropy: {} entered, {:.1} bits credited",
+        user_entropy.count(),
+        user_entropy.credited_bits()
+    );
+    Ok(user_entropy.additional_input())
+}
 
-mod bitvector;
+/// Settings of the combined source from the command line. The external sources are opened
+/// here, once, so that every seeding reads further into them.
+fn combined(
+    cli: &cli::Cli,
+    user_entropy: Option<Zeroizing<Vec<u8>>>,
+) -> Result<Combined, Box<dyn std::error::Error>> {
+    let seed_file = match &cli.seed_file {
+        Some(Some(path)) => Some(SeedFile::new(path)),
+        Some(None) => Some(SeedFile::default_location()?),
+        None => None,
+    };
+    let external = cli
+        .external
+        .iter()
+        .map(External::open)
+        .collect::<Result<_, _>>()?;
+
+    Ok(Combined {
+        mechanism: cli.drbg,
+        rdseed: cli.rdseed,
+        hardware: cli.hardware,
+        cpujitter: CpuJitter::new(cli.jitter_min_entropy)?,
+        policy: cli.sources,
+        debug: cli.debug,
+        paranoid: cli.paranoid,
+        reseed: ReseedPolicy {
+            requests: cli.reseed_requests,
+            bytes: cli.reseed_bytes,
+            interval: Duration::from_secs(cli.reseed_seconds),
+        },
+        seed_file,
+        user_entropy,
+        external,
+    })
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = cli::Cli::parse();
+    let matches = cli::Cli::command().get_matches();
+    let cli = cli::Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    cli.check_combined_options(&matches);
+    cli.check_battery_size();
+
+    let user_entropy = cli
+        .user_entropy
+        .map(|kind| read_user_entropy(&cli, kind))
+        .transpose()?;
+    let combined = combined(&cli, user_entropy)?;
+
+    if let Some(rngtest) = &cli.rngtest {
+        let generator = rngtest.generator(combined)?;
+        if cli.battery {
+            let mut data = Zeroizing::new(vec![0u8; 8 * usize::try_from(cli.size())?]);
+            generator.try_fill_bytes(&mut data)?;
+            let report = keygen::battery::run(&data)?;
+
+            if cli.json {
+                println!("{}", report.to_json());
+            } else {
+                println!("Source: {rngtest:?}");
+                println!("{report}");
+            }
+            if !report.passed() {
+                return Err(keygen::battery::Error::Failed(report.failures()).into());
+            }
+            return Ok(());
+        }
 
-    if let Some(rngtest) = cli.rngtest {
-        let generator = rngtest;
-        let data_size = cli.size;
         let data_format = cli.format;
 
-        let num_values = u64::from(data_size);
+        // Filled a buffer at a time, so that the sources are not called for every word
+        let mut buffer = Zeroizing::new(vec![0u8; 8 * RNGTEST_BUFFER_WORDS]);
+        let mut remaining = usize::try_from(cli.size())?;
 
-        for _ in 0..num_values {
-            match generator.generate_u64() {
-                Ok(value) => {
-                    data_format.print_formatted_value(value);
-                }
-                Err(e) => return Err(e.into()),
+        while remaining > 0 {
+            let words = remaining.min(RNGTEST_BUFFER_WORDS);
+            let bytes = &mut buffer[..8 * words];
+            generator.try_fill_bytes(bytes)?;
+            for word in bytes.chunks_exact(8) {
+                data_format.print_formatted_value(u64::from_be_bytes(word.try_into()?));
             }
+            remaining -= words;
         }
 
         return Ok(());
     }
 
+    if let Some(source) = &cli.entropy_estimate {
+        let generator = source.generator(combined)?;
+        let samples =
+            keygen::estimate::collect_samples(generator.as_ref(), cli.samples, cli.sample_bits)?;
+
+        println!("Source: {source:?}");
+        println!("{}", keygen::estimate::estimate(&samples, cli.sample_bits)?);
+
+        return Ok(());
+    }
+
     let alphabet: Box<dyn Alphabet> = cli.alphabet.into();
+    let cpu_source = combined.cpu_source();
+    let generator: Box<dyn Generator + Sync> = match &cli.seed {
+        Some(seed) => {
+            let seeded = Seeded::new(seed, cli.allow_insecure_seed)?;
+            eprintln!(
+                "Warning: generating deterministically from --seed, the output is not secret"
+            );
+            Box::new(seeded)
+        }
+        None => cli.source.generator(combined)?,
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = &cli.egd_server {
+        let listener = keygen::egd::bind(path)?;
+        eprintln!("Serving EGD on {} from {:?}", path.display(), cli.source);
+        keygen::egd::serve(&listener, generator.as_ref())?;
+        return Ok(());
+    }
 
     if cli.debug {
         println!("Using alphabet: {:?}", cli.alphabet);
+        match cli.seed {
+            Some(_) => println!("Using source: seeded (insecure)"),
+            None => println!("Using source: {:?}", cli.source),
+        }
+        println!("Using DRBG: {:?}", cli.drbg);
+        println!("Using CPU source: {:?}", cpu_source?);
+        match keygen::random::entropy_avail() {
+            Some(entropy_avail) => {
+                println!("Kernel entropy_avail (informational): {entropy_avail}")
+            }
+            None => println!("Kernel entropy_avail (informational): unavailable"),
+        }
         println!("alphabet_count: {}", alphabet.count());
         println!("request bits: {}", cli.bits);
     }
 
     // Find the number of characters needed
     let bits_per_element = alphabet.bits_per_element();
-    #[allow(
-        clippy::cast_possible_truncation,
-        clippy::cast_lossless,
-        clippy::cast_sign_loss
-    )]
-    let num_elements = (cli.bits as f64 / bits_per_element).ceil() as u32;
+    let num_elements = keygen::password::element_count(alphabet.as_ref(), cli.bits);
 
     if cli.debug {
         println!("Bits per element: {bits_per_element}");
@@ -62,29 +180,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create the password(s)
     for _ in 0..cli.count {
-        let mut password_string = String::new();
-
-        for i in 0..num_elements {
-            // pull out a random value that does not result in modulo bias
-            let random_value = {
-                loop {
-                    let val = random::OsRand {}.generate_u64()?;
-                    if val <= (u64::MAX - (alphabet.count() as u64)) {
-                        break val;
-                    }
-                }
-            };
-
-            // get the corresponding alphabet element
-            let random_index = usize::try_from(random_value % alphabet.count() as u64)?;
-            let random_element = alphabet.item(random_index)?;
-            password_string.push_str(&random_element);
-            if i < num_elements - 1 {
-                password_string.push_str(&cli.delimiter);
-            }
-        }
-
-        println!("{password_string}");
+        let password_string = keygen::generate_password(
+            generator.as_ref(),
+            alphabet.as_ref(),
+            cli.bits,
+            &cli.delimiter,
+        )?;
+
+        println!("{}", *password_string);
     }
 
     Ok(())
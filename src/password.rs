@@ -0,0 +1,129 @@
+//! High-level password generation
+
+use zeroize::Zeroizing;
+
+use crate::alphabet::Alphabet;
+use crate::random::{self, Generator};
+
+#[derive(thiserror::Error, Debug)]
+/// Errors for password generation
+pub enum Error {
+    #[error("Alphabet error: {0}")]
+    Alphabet(#[from] crate::alphabet::Error),
+    #[error("Random number generation error: {0}")]
+    Random(#[from] crate::random::Error),
+    #[error("Random index does not fit the alphabet")]
+    IndexConversion(#[from] std::num::TryFromIntError),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the number of alphabet elements needed to reach at least `bits` bits of entropy.
+#[must_use]
+pub fn element_count(alphabet: &dyn Alphabet, bits: u32) -> u32 {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_lossless,
+        clippy::cast_sign_loss
+    )]
+    let num_elements = (bits as f64 / alphabet.bits_per_element()).ceil() as u32;
+    num_elements
+}
+
+/// Appends `part` to `password`, moving it to a larger buffer when needed.
+///
+/// `String` would reallocate without wiping the old buffer, so the growing is done here and the
+/// old buffer is erased when it is dropped.
+fn push_zeroizing(password: &mut Zeroizing<String>, part: &str) {
+    let required = password.len() + part.len();
+    if required > password.capacity() {
+        let mut grown =
+            Zeroizing::new(String::with_capacity(required.max(2 * password.capacity())));
+        grown.push_str(password);
+        *password = grown;
+    }
+    password.push_str(part);
+}
+
+/// Generates a single password of at least `bits` bits of entropy.
+///
+/// Each element is picked from `alphabet` using a value pulled from `generator`, and the elements
+/// are joined with `delimiter`. The password is erased from memory when it is dropped.
+///
+/// # Errors
+///
+/// This function will return an error if the generator fails or the alphabet cannot provide
+/// the selected element.
+pub fn generate_password(
+    generator: &dyn Generator,
+    alphabet: &dyn Alphabet,
+    bits: u32,
+    delimiter: &str,
+) -> Result<Zeroizing<String>> {
+    let num_elements = element_count(alphabet, bits);
+    let mut password_string = Zeroizing::new(String::new());
+
+    for i in 0..num_elements {
+        // pick an unbiased index into the alphabet
+        let random_index =
+            usize::try_from(random::uniform_below(generator, alphabet.count() as u64)?)?;
+        let random_element = Zeroizing::new(alphabet.item(random_index)?);
+        push_zeroizing(&mut password_string, &random_element);
+        if i < num_elements - 1 {
+            push_zeroizing(&mut password_string, delimiter);
+        }
+    }
+
+    Ok(password_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::Builtin;
+    use crate::random::Seeded;
+
+    #[test]
+    fn test_push_zeroizing() {
+        let mut password = Zeroizing::new(String::new());
+        for part in ["kissa", ".", "koira", ".", "a"] {
+            push_zeroizing(&mut password, part);
+        }
+        assert_eq!(password.as_str(), "kissa.koira.a");
+        assert!(password.capacity() >= password.len());
+    }
+
+    /// Passwords from the seeded source, so that changes in the picking of elements show up.
+    #[test]
+    fn test_golden_passwords() -> Result<(), Box<dyn std::error::Error>> {
+        let seed: Vec<u8> = (0..48).collect();
+        let golden = [
+            (Builtin::WordsFi, 64, "", "sanontakarstamirinhuolikieliasu"),
+            (Builtin::WordsFi, 128, "-", "sanonta-karsta-mirin-huoli-kieliasu-pilailla-ruudikas-khoi-lakimies"),
+            (Builtin::WordsFi, 256, " ", "sanonta karsta mirin huoli kieliasu pilailla ruudikas khoi lakimies karisma soukka sipaista nettailu etydi euribor kimmoisa valtio romsku"),
+            (Builtin::Commonsafe, 64, "", "Hcp6dwGdibL"),
+            (Builtin::Commonsafe, 128, "-", "H-c-p-6-d-w-G-d-i-b-L-J-r-1-1-e-U-F-2-K-h-t"),
+            (Builtin::Commonsafe, 256, " ", "H c p 6 d w G d i b L J r 1 1 e U F 2 K h t K G k j F . v L R X M v 7 G N 6 ! N Q k r s"),
+            (Builtin::Normal, 64, "", "JgtbhBIhngN"),
+            (Builtin::Normal, 128, "-", "J-g-t-b-h-B-I-h-n-g-N-L-w-6-6-i-W-H-6-M-m-x"),
+            (Builtin::Normal, 256, " ", "J g t b h B I h n g N L w 6 6 i W H 6 M m x L J p o H 4 z N T Z O A c I P b 0 P R p w"),
+            (Builtin::Ascii, 64, "", "f9M0;Zd;D8"),
+            (Builtin::Ascii, 128, "-", "f-9-M-0-;-Z-d-;-D-8-k-h-Q-)-)-<-y-c-*-i"),
+            (Builtin::Ascii, 256, " ", "f 9 M 0 ; Z d ; D 8 k h Q ) ) < y c * i B S i e G E b & W k t ~ l W 2 d n 1 !"),
+            (Builtin::Assembly, 64, "", "mätikantapäähätähillokäärösuorajokerikääröraffi"),
+            (Builtin::Assembly, 128, "-", "mäti-kantapää-hätä-hillo-käärö-suora-jokeri-käärö-raffi-häävi-aito-pöllö-laukku-hylje-hylje-naatti-höylä"),
+            (Builtin::Assembly, 256, " ", "mäti kantapää hätä hillo käärö suora jokeri käärö raffi häävi aito pöllö laukku hylje hylje naatti höylä lepakko dippi traktori pakki yskä traktori teekkari sämpylä lenssu lepakko tiltti kissa aito taksi monsteri hyppy"),
+        ];
+
+        for (builtin, bits, delimiter, expected) in golden {
+            let generator = Seeded::new(&seed, true)?;
+            let alphabet: Box<dyn Alphabet> = builtin.into();
+            let password = generate_password(&generator, alphabet.as_ref(), bits, delimiter)?;
+            assert_eq!(
+                password.as_str(),
+                expected,
+                "{builtin:?} {bits} {delimiter:?}"
+            );
+        }
+        Ok(())
+    }
+}
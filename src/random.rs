@@ -1,16 +1,26 @@
 use crate::bitvector::BitVector;
+use crate::drbg::{self, Drbg, Mechanism};
+use crate::external::ExternalSource;
+use crate::fork::CloneDetector;
+use crate::health::HealthTests;
 use crate::hmac_drbg::HmacDrbg;
+use crate::seedfile::{self, SeedFile};
 use clap::ValueEnum;
 use getrandom::getrandom;
 use lazy_static::lazy_static;
-use std::{arch::asm, fmt};
+#[cfg(target_arch = "x86_64")]
+use std::arch::asm;
+use std::cell::Cell;
+use std::fmt;
 use std::{
     str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tiny_keccak::Hasher;
 use tiny_keccak::Sha3;
-use zeroize::Zeroize;
+use zeroize::Zeroizing;
 
 #[allow(dead_code)]
 #[derive(thiserror::Error, Debug)]
@@ -18,22 +28,36 @@ use zeroize::Zeroize;
 pub enum Error {
     #[error("RDRAND failed")]
     RdrandFailed,
+    #[error("RDSEED failed")]
+    RdseedFailed,
+    #[error("RDRAND is not supported by this CPU")]
+    RdrandUnsupported,
+    #[error("RDSEED is not supported by this CPU")]
+    RdseedUnsupported,
+    #[error("{0} failed the health test and is considered broken")]
+    HardwareBroken(&'static str),
     #[error("OS random number generation failed")]
     OSRandFailed,
     #[error("Unable to create cpu jitter entropy. System too busy or idle?")]
     CpuJitterFailed,
-    #[error("Unable to determine current time")]
-    SystemTime(#[from] std::time::SystemTimeError),
-    #[error("Time went backwards")]
-    BackwardsTimeTravel,
+    #[error("CPU jitter health test failed: {0}")]
+    CpuJitterHealth(#[from] crate::health::Error),
     #[error("Unable to convert bytes to u64")]
     ByteConversion(#[from] std::array::TryFromSliceError),
-    #[error("Entropy pool is low {0}")]
-    NotEnoughEntropy(u64),
-    #[error("Unable to read entropy pool from /proc/sys/kernel/random/entropy_avail")]
-    EntropyPoolUnavailable,
-    #[error("HMAC DRBG error: {0}")]
-    HmacDrbg(#[from] crate::hmac_drbg::Error),
+    #[error("The kernel random number generator is not initialized yet, try again once the system has finished booting")]
+    OsRandNotReady,
+    #[error("DRBG error: {0}")]
+    Drbg(#[from] crate::drbg::Error),
+    #[error("Unable to pick a random value from an empty range")]
+    EmptyRange,
+    #[error("Source policy '{0}' is not satisfied: {1}")]
+    SourcePolicyNotSatisfied(SourcePolicy, String),
+    #[error("The seeded source is deterministic and insecure, it must be explicitly allowed")]
+    SeededNotAllowed,
+    #[error("{0}")]
+    External(#[from] crate::external::Error),
+    #[error("The external source needs exactly one external source configured, {0} given")]
+    ExternalSourceCount(usize),
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -41,31 +65,128 @@ fn vec_u8_to_u64(bytes: &[u8]) -> Result<u64> {
     Ok(u64::from_be_bytes(bytes.try_into()?))
 }
 
-#[cfg(target_os = "linux")]
-fn check_entropy_pool() -> Result<()> {
-    const MIN_ENTROPY_THRESHOLD: u64 = 200; // Adjust this threshold as needed
+/// Set once the kernel random number generator has been seen initialized, it stays so.
+static OS_RAND_READY: AtomicBool = AtomicBool::new(false);
 
-    if let Ok(entropy_avail) = read_to_string("/proc/sys/kernel/random/entropy_avail") {
-        let entropy_avail: u64 = entropy_avail.trim().parse().unwrap_or(0);
+/// Checks that the kernel random number generator is initialized.
+///
+/// `getrandom(GRND_NONBLOCK)` fails with EAGAIN until the kernel CRNG has been seeded. Kernels
+/// without the system call are left to the fallback of the `getrandom` crate, which waits for
+/// /dev/random to become readable before using /dev/urandom.
+#[cfg(target_os = "linux")]
+fn check_os_rand_ready() -> Result<()> {
+    if OS_RAND_READY.load(Ordering::Relaxed) {
+        return Ok(());
+    }
 
-        if entropy_avail < MIN_ENTROPY_THRESHOLD {
-            return Err(Error::NotEnoughEntropy(entropy_avail));
+    let mut byte = Zeroizing::new([0u8; 1]);
+    loop {
+        // SAFETY: the buffer is valid for writes of its length
+        let result = unsafe { libc::getrandom(byte.as_mut_ptr().cast(), 1, libc::GRND_NONBLOCK) };
+        if result == 1 {
+            break;
+        }
+        match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EAGAIN) => return Err(Error::OsRandNotReady),
+            Some(libc::ENOSYS) => break,
+            _ => return Err(Error::OSRandFailed),
         }
-    } else {
-        return Err(Error::EntropyPoolUnavailable);
     }
+
+    OS_RAND_READY.store(true, Ordering::Relaxed);
     Ok(())
 }
 
 #[allow(clippy::unnecessary_wraps)]
 #[cfg(not(target_os = "linux"))]
-const fn check_entropy_pool() -> Result<()> {
-    // On non-Linux systems (e.g., Windows), we can not check the amount of entropy available.
+fn check_os_rand_ready() -> Result<()> {
+    // Other systems block in getrandom() until their generator is seeded, or are always seeded.
+    OS_RAND_READY.store(true, Ordering::Relaxed);
     Ok(())
 }
 
+/// Returns the kernel entropy estimate from /proc/sys/kernel/random/entropy_avail.
+///
+/// This is informational only. Since Linux 5.18 the value is always 256, and it never said
+/// whether the random number generator was ready; that is checked with `getrandom()`.
+#[must_use]
+pub fn entropy_avail() -> Option<u64> {
+    std::fs::read_to_string("/proc/sys/kernel/random/entropy_avail")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 pub trait Generator {
     fn generate_u64(&self) -> Result<u64>;
+
+    /// Fills `dest` with random bytes.
+    ///
+    /// The default implementation takes the big-endian bytes of
+    /// [`generate_u64`](Self::generate_u64), 8 at a time. Sources that produce bytes directly
+    /// fill the whole buffer at once.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the source fails. `dest` may then be partly filled.
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<()> {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = Zeroizing::new(self.generate_u64()?.to_be_bytes());
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    /// Fills `dest` with random bytes, like [`try_fill_bytes`](Self::try_fill_bytes).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the source fails, for callers such as `rand_core::RngCore::fill_bytes` that
+    /// have no way to report an error.
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        if let Err(error) = self.try_fill_bytes(dest) {
+            panic!("Random number generation failed: {error}");
+        }
+    }
+}
+
+impl<G: Generator + ?Sized> Generator for &G {
+    fn generate_u64(&self) -> Result<u64> {
+        (**self).generate_u64()
+    }
+
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<()> {
+        (**self).try_fill_bytes(dest)
+    }
+}
+
+/// Returns an unbiased random number in the range `0..n`.
+///
+/// Uses Lemire's multiply-shift method: the 128-bit product of a random u64 and `n` is accepted
+/// only if its low 64 bits are at least `2^64 mod n`, so that every output value is produced by
+/// exactly `floor(2^64 / n)` generator outputs. Rejected values are simply drawn again.
+/// <https://arxiv.org/abs/1805.10941>
+///
+/// # Errors
+///
+/// This function will return an error if `n` is zero or the generator fails.
+pub fn uniform_below(generator: &dyn Generator, n: u64) -> Result<u64> {
+    if n == 0 {
+        return Err(Error::EmptyRange);
+    }
+
+    let threshold = n.wrapping_neg() % n;
+
+    loop {
+        let product = u128::from(generator.generate_u64()?) * u128::from(n);
+
+        #[allow(clippy::cast_possible_truncation)]
+        if product as u64 >= threshold {
+            return Ok((product >> 64) as u64);
+        }
+    }
 }
 
 /// Return U64 random number from the OS.
@@ -77,30 +198,60 @@ pub struct OsRand {}
 
 impl Generator for OsRand {
     fn generate_u64(&self) -> Result<u64> {
-        let mut random_bytes = [0u8; 8];
+        let mut random_bytes = Zeroizing::new([0u8; 8]);
 
-        check_entropy_pool()?;
+        check_os_rand_ready()?;
 
-        if getrandom(&mut random_bytes).is_ok() {
-            let random_u64 = u64::from_le_bytes(random_bytes);
+        if getrandom(random_bytes.as_mut()).is_ok() {
+            let random_u64 = u64::from_le_bytes(*random_bytes);
             Ok(random_u64)
         } else {
             Err(Error::OSRandFailed)
         }
     }
+
+    /// One `getrandom()` call for the whole buffer.
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<()> {
+        check_os_rand_ready()?;
+        getrandom(dest).map_err(|_| Error::OSRandFailed)
+    }
+}
+
+/// Returns true if the CPU supports the RDRAND instruction.
+#[must_use]
+pub fn rdrand_supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return std::arch::is_x86_feature_detected!("rdrand");
+    #[cfg(not(target_arch = "x86_64"))]
+    return false;
+}
+
+/// Returns true if the CPU supports the RDSEED instruction.
+#[must_use]
+pub fn rdseed_supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return std::arch::is_x86_feature_detected!("rdseed");
+    #[cfg(not(target_arch = "x86_64"))]
+    return false;
 }
 
 /// Return U64 random number from the CPU RDRAND instruction.
 ///
-/// If the CPU does not support RDRAND, the function will return an error.
-/// This effectively limits the program to only run on Intel & AMD CPUs.
+/// Support for the instruction is detected at run time with CPUID. If the CPU does not support
+/// RDRAND, or the target is not x86-64, the function will return [`Error::RdrandUnsupported`].
 pub struct RdRand {}
 
 impl Generator for RdRand {
+    #[cfg(target_arch = "x86_64")]
     fn generate_u64(&self) -> Result<u64> {
+        if !rdrand_supported() {
+            return Err(Error::RdrandUnsupported);
+        }
+
         let mut result: u64;
         let mut success: i8;
 
+        // SAFETY: the CPU supports RDRAND, checked above
         unsafe {
             asm!(
                 "rdrand {0}; setc {1}",
@@ -115,6 +266,152 @@ impl Generator for RdRand {
             Err(Error::RdrandFailed)
         }
     }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn generate_u64(&self) -> Result<u64> {
+        Err(Error::RdrandUnsupported)
+    }
+}
+
+/// Number of RDSEED attempts before giving up.
+///
+/// RDSEED fails with the carry flag clear when the entropy source has no conditioned output
+/// available, which is expected under load. Intel recommends retrying with a pause in between.
+#[cfg(target_arch = "x86_64")]
+const RDSEED_RETRIES: u32 = 1024;
+
+/// Return U64 random number from the CPU RDSEED instruction.
+///
+/// RDSEED returns output of the CPU entropy source conditioner, while RDRAND returns output of
+/// the DRBG seeded from it, so RDSEED is the one meant for seeding other DRBGs.
+/// If the CPU does not support RDSEED, or the target is not x86-64, the function will return
+/// [`Error::RdseedUnsupported`].
+pub struct RdSeed {}
+
+impl Generator for RdSeed {
+    #[cfg(target_arch = "x86_64")]
+    fn generate_u64(&self) -> Result<u64> {
+        if !rdseed_supported() {
+            return Err(Error::RdseedUnsupported);
+        }
+
+        for _ in 0..RDSEED_RETRIES {
+            let mut result: u64;
+            let mut success: i8;
+
+            // SAFETY: the CPU supports RDSEED, checked above
+            unsafe {
+                asm!(
+                    "rdseed {0}; setc {1}",
+                    out(reg) result,
+                    out(reg_byte) success,
+                );
+            }
+
+            if success != 0 {
+                return Ok(result);
+            }
+
+            std::hint::spin_loop();
+        }
+
+        Err(Error::RdseedFailed)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn generate_u64(&self) -> Result<u64> {
+        Err(Error::RdseedUnsupported)
+    }
+}
+
+/// Number of draws in the startup self-test of a CPU instruction.
+const HEALTH_SELF_TEST_DRAWS: usize = 16;
+
+/// Health tests for a CPU random number instruction.
+///
+/// Buggy firmware has made RDRAND return all ones forever while still setting the carry flag,
+/// so the carry flag alone does not tell that the output is random. Before the first value is
+/// returned, a startup self-test takes a number of draws that must all differ and must not be
+/// all zeros or all ones. After that every value is compared with the previous one, and a
+/// repeated value fails the instruction. Once failed, the instruction stays failed.
+pub struct HealthChecked<G: Generator> {
+    name: &'static str,
+    instruction: G,
+    state: Mutex<HealthState>,
+}
+
+#[derive(Clone, Copy)]
+enum HealthState {
+    Untested,
+    /// Passed the tests so far, with the previous value for the continuous test
+    Healthy(Option<u64>),
+    Broken,
+}
+
+impl<G: Generator> HealthChecked<G> {
+    pub const fn new(name: &'static str, instruction: G) -> Self {
+        Self {
+            name,
+            instruction,
+            state: Mutex::new(HealthState::Untested),
+        }
+    }
+
+    /// Startup self-test: the draws must be distinct and not all zeros or all ones.
+    fn run_self_test(&self) -> Result<bool> {
+        let mut draws = Vec::with_capacity(HEALTH_SELF_TEST_DRAWS);
+        for _ in 0..HEALTH_SELF_TEST_DRAWS {
+            let value = self.instruction.generate_u64()?;
+            if value == 0 || value == u64::MAX || draws.contains(&value) {
+                return Ok(false);
+            }
+            draws.push(value);
+        }
+        Ok(true)
+    }
+
+    /// Returns the previous value, running the startup self-test first if needed.
+    fn check(&self, state: &mut HealthState) -> Result<Option<u64>> {
+        match *state {
+            HealthState::Untested if self.run_self_test()? => Ok(None),
+            HealthState::Healthy(previous) => Ok(previous),
+            HealthState::Untested | HealthState::Broken => {
+                *state = HealthState::Broken;
+                Err(Error::HardwareBroken(self.name))
+            }
+        }
+    }
+
+    /// Runs the startup self-test, unless it has already been run.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the instruction is not supported or fails, or
+    /// [`Error::HardwareBroken`] if the output has not passed the tests.
+    pub fn self_test(&self) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+        let previous = self.check(&mut state)?;
+        *state = HealthState::Healthy(previous);
+        Ok(())
+    }
+}
+
+impl<G: Generator> Generator for HealthChecked<G> {
+    fn generate_u64(&self) -> Result<u64> {
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+        let previous = self.check(&mut state)?;
+
+        let value = self.instruction.generate_u64()?;
+        if previous == Some(value) {
+            *state = HealthState::Broken;
+            return Err(Error::HardwareBroken(self.name));
+        }
+        *state = HealthState::Healthy(Some(value));
+
+        Ok(value)
+    }
 }
 
 /// Return U64 random number from CPU jitter.
@@ -123,15 +420,30 @@ impl Generator for RdRand {
 /// Rationale for this is that the cpujitter is not 100% random, but it is still a good source of entropy.
 /// Also, using the HMAC DRBG with the current personalization string (*that contains the timestamp*)
 /// would result in difficulties when estimating the randomness of the generated random numbers.
-pub struct CpuJitter {}
+#[derive(Default)]
+pub struct CpuJitter {
+    raw: CpuJitterRaw,
+}
+
+impl CpuJitter {
+    /// Creates the source with health test cutoffs for the assumed min-entropy per raw sample.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the min-entropy is not in (0, 1].
+    pub fn new(min_entropy: f64) -> Result<Self> {
+        Ok(Self {
+            raw: CpuJitterRaw::new(min_entropy)?,
+        })
+    }
+}
 
 impl Generator for CpuJitter {
     fn generate_u64(&self) -> Result<u64> {
         // Let's take 512 (8 * 64) bits of cpujitter entropy
-        let mut combined_data = Vec::new();
-        let cpujitterraw = CpuJitterRaw {};
+        let mut combined_data = Zeroizing::new(Vec::with_capacity(64));
         for _ in 0..8 {
-            match cpujitterraw.generate_u64() {
+            match self.raw.generate_u64() {
                 Ok(raw_value) => {
                     combined_data.extend_from_slice(raw_value.to_be_bytes().as_ref());
                 }
@@ -141,23 +453,66 @@ impl Generator for CpuJitter {
 
         // Hash the combined data with SHA3 (Keccak)
         let mut sha3 = Sha3::v256();
-        let mut hash_result = [0u8; 32];
+        let mut hash_result = Zeroizing::new([0u8; 32]);
         sha3.update(&combined_data);
-        sha3.finalize(&mut hash_result);
+        sha3.finalize(hash_result.as_mut());
 
         // Return the first 64 bits as u64
         vec_u8_to_u64(&hash_result[..8])
     }
 }
 
+/// Assumed min-entropy per raw CPU jitter sample in bits.
+///
+/// Derived from the measured worst case of the raw bits, so that their normal output passes
+/// the health tests with the false positive rate of the specification. The SP 800-90B non-IID
+/// estimators over one million samples give 0.29 bits (t-Tuple) or more, but the bits are
+/// serially correlated over longer stretches than that: on a single core x86-64 machine with
+/// three busy processes, 192 million raw bits held runs of up to 385 equal bits, and the first
+/// value of a 1024 bit window came up as often as 1011 times. The Repetition Count Test cutoff
+/// 1 + ceil(20 / H) stays above the longest run for H up to 0.052, and the Adaptive Proportion
+/// Test cutoff stays above 1011 up to about 0.06. At 0.05 bits the cutoffs are 401 equal
+/// samples and 1014 of 1024, so a stuck timer, or one that gives the same bit 255 times in
+/// 256, still fails.
+pub const DEFAULT_JITTER_MIN_ENTROPY: f64 = 0.05;
+
 /// Returns U64 from collected CPU jitter. The amount of raw entropy is around 6bits / byte.
-pub struct CpuJitterRaw {}
+///
+/// Every raw sample bit goes through the SP 800-90B Repetition Count Test and Adaptive
+/// Proportion Test, so that a degenerate timer is detected instead of producing predictable
+/// output. The tests run over all samples taken from the same instance.
+pub struct CpuJitterRaw {
+    health: Mutex<HealthTests>,
+}
+
+impl CpuJitterRaw {
+    /// Creates the source with health test cutoffs for the assumed min-entropy per sample.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the min-entropy is not in (0, 1].
+    pub fn new(min_entropy: f64) -> Result<Self> {
+        Ok(Self {
+            health: Mutex::new(HealthTests::new(min_entropy)?),
+        })
+    }
+}
+
+impl Default for CpuJitterRaw {
+    fn default() -> Self {
+        #[allow(clippy::unwrap_used)]
+        Self::new(DEFAULT_JITTER_MIN_ENTROPY).unwrap()
+    }
+}
 
 impl Generator for CpuJitterRaw {
     fn generate_u64(&self) -> Result<u64> {
         let mut bit_vector = BitVector::new();
         let mut loop_count = 0;
 
+        #[allow(clippy::unwrap_used)]
+        let mut health = self.health.lock().unwrap();
+
         loop {
             let start = std::time::Instant::now();
             let end = std::time::Instant::now();
@@ -168,11 +523,9 @@ impl Generator for CpuJitterRaw {
             let time_diff2 = end.duration_since(start).as_nanos();
 
             if time_diff1 != time_diff2 {
-                if time_diff1 > time_diff2 {
-                    bit_vector.add_bit(true);
-                } else {
-                    bit_vector.add_bit(false);
-                }
+                let sample = time_diff1 > time_diff2;
+                health.process(sample)?;
+                bit_vector.add_bit(sample);
             }
 
             // Check if the BitVector is full or if the loop count has reached maximum number of tries.
@@ -192,88 +545,613 @@ impl Generator for CpuJitterRaw {
 }
 
 lazy_static! {
-    static ref PREVIOUS_TIMESTAMP: std::sync::Mutex<(u64, u32)> = std::sync::Mutex::new((0, 0));
+    static ref DRBG_SELF_TEST_PASSED: bool = drbg::self_test().is_ok();
+    static ref RDRAND: HealthChecked<RdRand> = HealthChecked::new("RDRAND", RdRand {});
+    static ref RDSEED: HealthChecked<RdSeed> = HealthChecked::new("RDSEED", RdSeed {});
 }
 
-/// Personalization string combines a fixed string ("kissa123", Finnish for cat123) and both seconds and nanoseconds
-/// of current timestamp. This ensures that the personalization string is unique for each call.
-fn generate_personalization_string() -> Result<[u8; 32]> {
-    let mut personalization_string: [u8; 32] = [0; 32];
+/// Counter that makes every personalization string of the process unique.
+static PERSONALIZATION_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
-    let timestamp_secs = timestamp.as_secs();
-    let timestamp_nanos = timestamp.subsec_nanos();
+/// Time since the monotonic clock's starting point, CLOCK_MONOTONIC on Linux.
+#[cfg(target_os = "linux")]
+fn monotonic_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: the timespec is valid for writes
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    let duration = Duration::new(time.tv_sec as u64, time.tv_nsec as u32);
+    duration
+}
 
-    // Compare with previous timestamp
-    {
-        #[allow(clippy::unwrap_used)]
-        let mut prev_timestamp = PREVIOUS_TIMESTAMP.lock().unwrap();
-        if timestamp_secs < prev_timestamp.0
-            || (timestamp_secs == prev_timestamp.0 && timestamp_nanos <= prev_timestamp.1)
-        {
-            return Err(Error::BackwardsTimeTravel);
+/// Time since the monotonic clock's starting point, the first call on other systems.
+#[cfg(not(target_os = "linux"))]
+fn monotonic_time() -> Duration {
+    lazy_static! {
+        static ref START: Instant = Instant::now();
+    }
+    START.elapsed()
+}
+
+/// Identifies the boot, so that strings from different boots differ even if the clocks do not.
+fn boot_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id").unwrap_or_default()
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
+
+/// Appends a variable length field with its length, so that the fields can not run together.
+fn push_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(field);
+}
+
+/// Personalization string that is unique for each call. (SP 800-90A 8.7.1)
+///
+/// Combines a fixed string ("kissa123", Finnish for cat123), a counter, the process id, the boot
+/// id, the monotonic and wall clock times and the hostname. The counter makes the string unique
+/// within the process and the process and boot ids across processes and boots, so clock steps
+/// and coarse clocks do not matter and the string never fails.
+fn generate_personalization_string() -> Zeroizing<Vec<u8>> {
+    let mut personalization_string = Zeroizing::new(Vec::with_capacity(128));
+
+    personalization_string.extend_from_slice(b"kissa123");
+    let counter = PERSONALIZATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+    personalization_string.extend_from_slice(&counter.to_le_bytes());
+    personalization_string.extend_from_slice(&std::process::id().to_le_bytes());
+
+    // A wall clock before 1970 is as good as any other value here
+    let realtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    for time in [monotonic_time(), realtime] {
+        personalization_string.extend_from_slice(&time.as_secs().to_le_bytes());
+        personalization_string.extend_from_slice(&time.subsec_nanos().to_le_bytes());
+    }
+
+    push_field(&mut personalization_string, boot_id().as_bytes());
+    push_field(&mut personalization_string, hostname().as_bytes());
+
+    personalization_string
+}
+
+/// What the combined source does when the CPU random number instructions are not supported
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HardwarePolicy {
+    /// Seed from the OS and CPU jitter only
+    #[default]
+    Skip,
+    /// Fail instead of seeding without the CPU source
+    Require,
+}
+
+/// Which sources must contribute to the seed of the combined source
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SourcePolicy {
+    /// Every source in use must contribute
+    #[default]
+    All,
+    /// At least this many sources must contribute
+    AtLeast(usize),
+    /// OS random and at least one other source must contribute
+    OsPlusOne,
+}
+
+impl SourcePolicy {
+    /// Returns true if the contributing sources satisfy the policy.
+    fn satisfied(self, contributors: &[Source], failures: usize) -> bool {
+        match self {
+            Self::All => failures == 0,
+            Self::AtLeast(count) => contributors.len() >= count,
+            Self::OsPlusOne => contributors.contains(&Source::Os) && contributors.len() >= 2,
         }
+    }
+}
 
-        *prev_timestamp = (timestamp_secs, timestamp_nanos);
+impl fmt::Display for SourcePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::AtLeast(count) => write!(f, "{count}"),
+            Self::OsPlusOne => write!(f, "os-plus-one"),
+        }
+    }
+}
+
+impl FromStr for SourcePolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "os-plus-one" => Ok(Self::OsPlusOne),
+            _ => match s.parse() {
+                Ok(count) if count > 0 => Ok(Self::AtLeast(count)),
+                _ => Err(format!(
+                    "Unknown source policy: {s} (expected all, os-plus-one or a number of sources)"
+                )),
+            },
+        }
+    }
+}
+
+/// Seed material drawn from one source of the combined seed.
+type Draw = (Source, Result<Zeroizing<Vec<u8>>>);
+
+/// Draws 8 * 64 = 512 bits from a source.
+fn draw(generator: &dyn Generator) -> Result<Zeroizing<Vec<u8>>> {
+    let mut bytes = Zeroizing::new(vec![0; 8 * 8]);
+    generator.try_fill_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Seed of the combined source and the sources that did and did not contribute to it.
+struct CombinedSeed {
+    seed: Zeroizing<Vec<u8>>,
+    contributors: Vec<Source>,
+    failures: Vec<(Source, Error)>,
+}
+
+/// Concatenates the seed material of the sources that worked, if they satisfy the policy.
+fn combine_draws(policy: SourcePolicy, draws: Vec<Draw>) -> Result<CombinedSeed> {
+    let mut seed = Zeroizing::new(Vec::new());
+    let mut contributors = Vec::new();
+    let mut failures = Vec::new();
+
+    for (source, result) in draws {
+        match result {
+            Ok(bytes) => {
+                seed.extend_from_slice(&bytes);
+                contributors.push(source);
+            }
+            Err(error) => failures.push((source, error)),
+        }
+    }
+
+    if !policy.satisfied(&contributors, failures.len()) {
+        let failed: Vec<String> = failures
+            .iter()
+            .map(|(source, error)| format!("{source:?}: {error}"))
+            .collect();
+        return Err(Error::SourcePolicyNotSatisfied(policy, failed.join(", ")));
+    }
+
+    Ok(CombinedSeed {
+        seed,
+        contributors,
+        failures,
+    })
+}
+
+/// Selects the CPU source of the combined seed.
+///
+/// RDSEED is used when requested and supported, RDRAND otherwise. Without either of them the
+/// CPU source is skipped or an error returned, depending on the policy.
+fn select_cpu_source(
+    rdseed: bool,
+    rdseed_supported: bool,
+    rdrand_supported: bool,
+    policy: HardwarePolicy,
+) -> Result<Option<Source>> {
+    if rdseed && rdseed_supported {
+        Ok(Some(Source::Rdseed))
+    } else if rdrand_supported {
+        Ok(Some(Source::Rdrand))
+    } else if policy == HardwarePolicy::Skip {
+        Ok(None)
+    } else if rdseed {
+        Err(Error::RdseedUnsupported)
+    } else {
+        Err(Error::RdrandUnsupported)
     }
+}
 
-    // Copy the string bytes
-    let hardcoded_str = "kissa123";
-    personalization_string[..hardcoded_str.len()].copy_from_slice(hardcoded_str.as_bytes());
+/// Default number of requests served by the combined DRBG before it is reseeded.
+pub const DEFAULT_RESEED_REQUESTS: u64 = 1 << 16;
 
-    // Copy the timestamp seconds bytes
-    let secs_range = hardcoded_str.len()..hardcoded_str.len() + 8;
-    personalization_string[secs_range].copy_from_slice(&timestamp_secs.to_le_bytes());
+/// Default number of bytes served by the combined DRBG before it is reseeded.
+pub const DEFAULT_RESEED_BYTES: u64 = 1 << 20;
 
-    // Copy the timestamp nanoseconds bytes
-    let nanos_range = hardcoded_str.len() + 8..hardcoded_str.len() + 12;
-    personalization_string[nanos_range].copy_from_slice(&timestamp_nanos.to_le_bytes());
+/// Default number of seconds after which the combined DRBG is reseeded.
+pub const DEFAULT_RESEED_SECONDS: u64 = 60;
 
-    Ok(personalization_string)
+/// When the long-lived combined DRBG is reseeded from the sources
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReseedPolicy {
+    /// Reseed after this many requests
+    pub requests: u64,
+    /// Reseed after this many bytes of output
+    pub bytes: u64,
+    /// Reseed when this much time has passed since the last seeding
+    pub interval: Duration,
+}
+
+impl Default for ReseedPolicy {
+    fn default() -> Self {
+        Self {
+            requests: DEFAULT_RESEED_REQUESTS,
+            bytes: DEFAULT_RESEED_BYTES,
+            interval: Duration::from_secs(DEFAULT_RESEED_SECONDS),
+        }
+    }
 }
 
 /// Generate a random u64 combining three different sources
 ///
-/// Generate a 1536 bit seed from three different random number sources.
-/// Thats 8 * 64 = 512 bits from each source.
-pub struct Combined {}
+/// Generate a 1536 bit seed from three different random number sources and use it to
+/// instantiate the selected DRBG mechanism. Sources that fail are left out of the seed as long
+/// as the source policy is satisfied.
+#[derive(Default)]
+pub struct Combined {
+    pub mechanism: Mechanism,
+    /// Use RDSEED instead of RDRAND as the CPU source
+    pub rdseed: bool,
+    /// What to do when the CPU does not support RDRAND or RDSEED
+    pub hardware: HardwarePolicy,
+    /// CPU jitter source used for every seeding, so that its continuous health tests run over
+    /// all of its samples
+    pub cpujitter: CpuJitter,
+    /// Which sources must contribute to the seed
+    pub policy: SourcePolicy,
+    /// Report the sources contributing to each seed on stderr
+    pub debug: bool,
+    /// Seed a new DRBG for every value instead of reseeding a long-lived one
+    pub paranoid: bool,
+    /// When the long-lived DRBG is reseeded
+    pub reseed: ReseedPolicy,
+    /// Seed file mixed into the first DRBG instantiated and replaced with its output
+    pub seed_file: Option<SeedFile>,
+    /// User supplied entropy mixed into every new DRBG
+    pub user_entropy: Option<Zeroizing<Vec<u8>>>,
+    /// Opened external sources contributing to the seed in addition to the three sources. Each
+    /// seeding reads the next bytes, so a source that runs out fails the draw.
+    pub external: Vec<ExternalSource>,
+}
+
+impl Combined {
+    /// Returns the CPU source used for the seed, or `None` if it is skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the CPU source is required but not supported.
+    pub fn cpu_source(&self) -> Result<Option<Source>> {
+        select_cpu_source(
+            self.rdseed,
+            rdseed_supported(),
+            rdrand_supported(),
+            self.hardware,
+        )
+    }
+
+    /// Collect a 1536 bit seed from three different random number sources.
+    ///
+    /// Thats 8 * 64 = 512 bits from each source. Without a CPU source the seed is 1024 bits.
+    /// Degraded operation, where a source failed but the policy is still satisfied, is
+    /// reported on stderr.
+    fn seed(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let mut draws = vec![(Source::Os, draw(&OsRand {}))];
+        if let Some(source) = self.cpu_source()? {
+            let cpu = source.generator(Self::default())?;
+            draws.push((source, draw(cpu.as_ref())));
+        }
+        draws.push((Source::Cpujitter, draw(&self.cpujitter)));
+        for external in &self.external {
+            draws.push((Source::External, draw(external)));
+        }
+
+        let CombinedSeed {
+            seed,
+            contributors,
+            failures,
+        } = combine_draws(self.policy, draws)?;
+
+        for (source, error) in &failures {
+            eprintln!(
+                "Warning: combined source degraded, {source:?} left out of the seed: {error}"
+            );
+        }
+        if self.debug {
+            eprintln!("Seeded from: {contributors:?}");
+        }
+
+        Ok(seed)
+    }
 
+    /// Instantiate the DRBG from a fresh seed.
+    fn instantiate(&self) -> Result<Box<dyn Drbg + Send>> {
+        // The DRBG known-answer test is run once, before the first use
+        if !*DRBG_SELF_TEST_PASSED {
+            return Err(drbg::Error::SelfTestFailed.into());
+        }
+
+        let seed = self.seed()?;
+
+        // Generate a deterministic, but each time unique, personalization string
+        let personalization_string = generate_personalization_string();
+
+        let mut drbg = self
+            .mechanism
+            .instantiate(&seed, &[], &personalization_string[..])?;
+        // The paranoid mode instantiates a DRBG for every value, the seed file is used once
+        let seed_file = self
+            .seed_file
+            .as_ref()
+            .filter(|seed_file| seed_file.first_use());
+        if seed_file.is_some() || self.user_entropy.is_some() {
+            self.mix_additional_input(drbg.as_mut(), seed_file)?;
+        }
+
+        Ok(drbg)
+    }
+
+    /// Mixes the seed file and the user entropy into the DRBG as additional input, and
+    /// replaces the seed file with the output.
+    ///
+    /// The seed file is defence in depth, so failing to read or write it is only a warning.
+    fn mix_additional_input(
+        &self,
+        drbg: &mut dyn Drbg,
+        seed_file: Option<&SeedFile>,
+    ) -> Result<()> {
+        let previous = seed_file.and_then(|seed_file| {
+            seed_file.read().unwrap_or_else(|error| {
+                let path = seed_file.path().display();
+                eprintln!("Warning: seed file {path} not used: {error}");
+                None
+            })
+        });
+
+        let mut additional_input = Zeroizing::new(Vec::new());
+        for field in [previous.as_deref(), self.user_entropy.as_deref()] {
+            push_field(&mut additional_input, field.map_or(&[], |field| &field[..]));
+        }
+
+        let mut next = Zeroizing::new([0u8; seedfile::SEED_SIZE]);
+        drbg.generate(next.as_mut(), &additional_input)?;
+
+        if let Some(seed_file) = seed_file {
+            let path = seed_file.path().display();
+            if let Err(error) = seed_file.write(next.as_ref()) {
+                eprintln!("Warning: seed file {path} not updated: {error}");
+            } else if self.debug {
+                eprintln!("Mixed in and updated seed file {path}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill `output` from the DRBG, reseeding it from the combined sources when the reseed interval is reached.
+    fn generate_reseeding(&self, drbg: &mut dyn Drbg, output: &mut [u8]) -> Result<()> {
+        for chunk in output.chunks_mut(drbg::MAX_BYTES_PER_REQUEST) {
+            match drbg.generate(chunk, &[]) {
+                Err(drbg::Error::ReseedIntervalReached) => {
+                    let seed = self.seed()?;
+                    drbg.reseed(&seed, &[])?;
+                    drbg.generate(chunk, &[])?;
+                }
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The paranoid mode: a new DRBG seeded from all sources for every value.
 impl Generator for Combined {
+    /// Every value comes from a new DRBG, and so does every 8 bytes of a filled buffer.
     fn generate_u64(&self) -> Result<u64> {
-        let mut seed: Vec<u8> = Vec::new();
+        let mut drbg = self.instantiate()?;
+        let mut random_bytes = Zeroizing::new([0u8; 8]);
+        self.generate_reseeding(drbg.as_mut(), random_bytes.as_mut())?;
+        Ok(u64::from_be_bytes(*random_bytes))
+    }
+}
 
-        let osrand = OsRand {};
-        let rdrand = RdRand {};
-        let cpujitter = CpuJitter {};
+/// A DRBG seeded from the combined sources and the use since the last seeding.
+struct Instance {
+    drbg: Box<dyn Drbg + Send>,
+    requests: u64,
+    bytes: u64,
+    seeded_at: Instant,
+    detector: CloneDetector,
+}
 
-        for _ in 0..8 {
-            seed.extend_from_slice(&osrand.generate_u64()?.to_be_bytes());
-            seed.extend_from_slice(&rdrand.generate_u64()?.to_be_bytes());
-            seed.extend_from_slice(&cpujitter.generate_u64()?.to_be_bytes());
+impl Instance {
+    fn new(drbg: Box<dyn Drbg + Send>) -> Self {
+        Self {
+            drbg,
+            requests: 0,
+            bytes: 0,
+            seeded_at: Instant::now(),
+            detector: CloneDetector::new(),
         }
+    }
 
-        // Generate a deterministic, but each time unique, personalization string
-        let mut personalization_string: [u8; 32] = generate_personalization_string()?;
+    /// Starts counting the use since the last seeding again.
+    fn reseeded(&mut self) {
+        self.requests = 0;
+        self.bytes = 0;
+        self.seeded_at = Instant::now();
+    }
+
+    /// Returns true if any of the reseed limits has been reached.
+    fn reseed_due(&self, policy: &ReseedPolicy) -> bool {
+        self.requests >= policy.requests
+            || self.bytes >= policy.bytes
+            || self.seeded_at.elapsed() >= policy.interval
+    }
+}
+
+/// Generate random u64s from a long-lived DRBG seeded from the combined sources
+///
+/// The DRBG is seeded on first use and reseeded from all sources when the reseed policy says
+/// so, when a reseed is requested, and before the first request after the process forks or
+/// the VM generation changes.
+pub struct CombinedDrbg {
+    combined: Combined,
+    state: Mutex<Option<Instance>>,
+    reseed_requested: AtomicBool,
+}
+
+impl CombinedDrbg {
+    #[must_use]
+    pub const fn new(combined: Combined) -> Self {
+        Self {
+            combined,
+            state: Mutex::new(None),
+            reseed_requested: AtomicBool::new(false),
+        }
+    }
+
+    /// Makes the next request reseed the DRBG from the sources.
+    pub fn request_reseed(&self) {
+        self.reseed_requested.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Generator for CombinedDrbg {
+    fn generate_u64(&self) -> Result<u64> {
+        let mut random_bytes = Zeroizing::new([0u8; 8]);
+        self.try_fill_bytes(random_bytes.as_mut())?;
+        Ok(u64::from_be_bytes(*random_bytes))
+    }
+
+    /// Each request of at most the maximum DRBG request size counts as one request for the
+    /// reseed policy, so a large buffer is reseeded part way as needed.
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+
+        for chunk in dest.chunks_mut(drbg::MAX_BYTES_PER_REQUEST) {
+            let instance = self.ready_instance(&mut state)?;
+            self.combined
+                .generate_reseeding(instance.drbg.as_mut(), chunk)?;
+            instance.requests += 1;
+            instance.bytes += chunk.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl CombinedDrbg {
+    /// Returns the DRBG, seeded or reseeded first if needed.
+    fn ready_instance<'a>(&self, state: &'a mut Option<Instance>) -> Result<&'a mut Instance> {
+        // A failed reseed drops the old DRBG, so the next request seeds a new one
+        let instance = match state.take() {
+            Some(mut instance) => {
+                // Checked on every request, so that a copy never serves the state it shares
+                let cloned = instance.detector.changed();
+                if cloned && self.combined.debug {
+                    eprintln!("Fork or VM clone detected, reseeding");
+                }
+
+                if cloned
+                    || self.reseed_requested.load(Ordering::SeqCst)
+                    || instance.reseed_due(&self.combined.reseed)
+                {
+                    let seed = self.combined.seed()?;
+                    let additional_input = generate_personalization_string();
+                    instance.drbg.reseed(&seed, &additional_input[..])?;
+                    self.reseed_requested.store(false, Ordering::SeqCst);
+                    instance.reseeded();
+                }
+                instance
+            }
+            None => {
+                let drbg = self.combined.instantiate()?;
+                self.reseed_requested.store(false, Ordering::SeqCst);
+                Instance::new(drbg)
+            }
+        };
+        Ok(state.insert(instance))
+    }
+}
 
-        // Generate the u64 random number using HMAC DRBG
-        let mut drbg = HmacDrbg::new(&seed, &personalization_string);
-        let random_bytes = drbg.generate_bytes(8)?;
-        let random_value = vec_u8_to_u64(&random_bytes);
+/// Personalization string of the seeded source, fixed so that the output only depends on the seed.
+const SEEDED_PERSONALIZATION: &[u8] = b"keygen seeded";
 
-        personalization_string.zeroize();
-        seed.zeroize();
+/// Generate random u64s deterministically from a caller-provided seed
+///
+/// An HMAC_DRBG with SHA-256 instantiated from the seed alone, without any live entropy. The
+/// same seed always gives the same values, which makes password generation reproducible for
+/// tests and bug reports. **Not for production use**: anyone who knows the seed knows every
+/// value. The DRBG is never reseeded, so it fails after the reseed interval of the DRBG.
+pub struct Seeded {
+    drbg: Mutex<HmacDrbg>,
+}
 
-        random_value
+impl Seeded {
+    /// Instantiates the seeded source. `allow_insecure` must be true to acknowledge that the
+    /// output is predictable from the seed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if insecure generation is not allowed, or if the seed
+    /// is shorter than 48 bytes, 1.5 times the security strength of HMAC_DRBG with SHA-256.
+    pub fn new(seed: &[u8], allow_insecure: bool) -> Result<Self> {
+        if !allow_insecure {
+            return Err(Error::SeededNotAllowed);
+        }
+        Ok(Self {
+            drbg: Mutex::new(HmacDrbg::new(seed, SEEDED_PERSONALIZATION)?),
+        })
     }
 }
 
-#[derive(ValueEnum, Clone)]
+impl Generator for Seeded {
+    fn generate_u64(&self) -> Result<u64> {
+        let mut random_bytes = Zeroizing::new([0u8; 8]);
+        self.try_fill_bytes(random_bytes.as_mut())?;
+        Ok(u64::from_be_bytes(*random_bytes))
+    }
+
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let mut drbg = self.drbg.lock().unwrap();
+        for chunk in dest.chunks_mut(drbg::MAX_BYTES_PER_REQUEST) {
+            drbg.generate(chunk, &[])?;
+        }
+        Ok(())
+    }
+}
+
+/// Small non-cryptographic generator (SplitMix64), for shuffles and statistical tests.
+pub(crate) struct SplitMix64(Cell<u64>);
+
+impl SplitMix64 {
+    pub(crate) const fn new(seed: u64) -> Self {
+        Self(Cell::new(seed))
+    }
+}
+
+impl Generator for SplitMix64 {
+    fn generate_u64(&self) -> Result<u64> {
+        let state = self.0.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.0.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Ok(z ^ (z >> 31))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
 pub enum Source {
     Combined,
     Rdrand,
+    Rdseed,
     Os,
     Cpujitter,
     CpujitterRaw,
+    External,
 }
 
 impl fmt::Debug for Source {
@@ -281,9 +1159,11 @@ impl fmt::Debug for Source {
         match self {
             Self::Combined => write!(f, "combined"),
             Self::Rdrand => write!(f, "rdrand"),
+            Self::Rdseed => write!(f, "rdseed"),
             Self::Os => write!(f, "os"),
             Self::Cpujitter => write!(f, "cpujitter"),
             Self::CpujitterRaw => write!(f, "cpujitter-raw"),
+            Self::External => write!(f, "external"),
         }
     }
 }
@@ -294,22 +1174,565 @@ impl FromStr for Source {
         match s {
             "combined" => Ok(Self::Combined),
             "rdrand" => Ok(Self::Rdrand),
+            "rdseed" => Ok(Self::Rdseed),
             "os" => Ok(Self::Os),
             "cpujitter" => Ok(Self::Cpujitter),
             "cpujitter-raw" => Ok(Self::CpujitterRaw),
+            "external" => Ok(Self::External),
             _ => Err(format!("Unknown source: {s}")),
         }
     }
 }
-impl Generator for Source {
-    fn generate_u64(&self) -> Result<u64> {
-        let source: Box<&dyn Generator> = match self {
-            Self::Combined => Box::new(&Combined {}),
-            Self::Rdrand => Box::new(&RdRand {}),
-            Self::Os => Box::new(&OsRand {}),
-            Self::Cpujitter => Box::new(&CpuJitter {}),
-            Self::CpujitterRaw => Box::new(&CpuJitterRaw {}),
+impl Source {
+    /// Returns the generator of the source. The combined source uses the given settings, and
+    /// the external source is the one external source in them.
+    ///
+    /// The generator carries the state of the source, such as the seeded DRBG and the health
+    /// tests, so it is created once and used for every value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the external source is not exactly one.
+    pub fn generator(&self, combined: Combined) -> Result<Box<dyn Generator + Sync>> {
+        Ok(match self {
+            Self::Combined if combined.paranoid => Box::new(combined),
+            Self::Combined => Box::new(CombinedDrbg::new(combined)),
+            Self::Rdrand => Box::new(&*RDRAND),
+            Self::Rdseed => Box::new(&*RDSEED),
+            Self::Os => Box::new(OsRand {}),
+            Self::Cpujitter => Box::new(combined.cpujitter),
+            Self::CpujitterRaw => Box::new(combined.cpujitter.raw),
+            Self::External => match <[ExternalSource; 1]>::try_from(combined.external) {
+                Ok([external]) => Box::new(external),
+                Err(externals) => return Err(Error::ExternalSourceCount(externals.len())),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::{Alphabet, Builtin};
+    use crate::external::External;
+    use crate::testutil::TempPath;
+    use std::cell::RefCell;
+
+    /// Generator returning a fixed sequence of values.
+    struct Sequence(RefCell<Vec<u64>>);
+
+    impl Generator for Sequence {
+        fn generate_u64(&self) -> Result<u64> {
+            Ok(self.0.borrow_mut().remove(0))
+        }
+    }
+
+    /// Mock instruction stuck at one value, like RDRAND on the buggy firmware.
+    struct Stuck(u64);
+
+    impl Generator for Stuck {
+        fn generate_u64(&self) -> Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    /// Mock instruction that is not supported.
+    struct Unsupported;
+
+    impl Generator for Unsupported {
+        fn generate_u64(&self) -> Result<u64> {
+            Err(Error::RdrandUnsupported)
+        }
+    }
+
+    /// Values that pass the startup self-test, followed by `rest`.
+    fn healthy_then(rest: &[u64]) -> Sequence {
+        let mut values: Vec<u64> = (1..=HEALTH_SELF_TEST_DRAWS as u64).collect();
+        values.extend_from_slice(rest);
+        Sequence(RefCell::new(values))
+    }
+
+    #[test]
+    fn test_health_checked_stuck_output() {
+        for stuck in [u64::MAX, 0, 0x1234_5678] {
+            let instruction = HealthChecked::new("RDRAND", Stuck(stuck));
+            assert!(matches!(
+                instruction.generate_u64(),
+                Err(Error::HardwareBroken("RDRAND"))
+            ));
+            assert!(matches!(
+                instruction.self_test(),
+                Err(Error::HardwareBroken("RDRAND"))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_health_checked_all_ones_in_self_test() {
+        let mut values: Vec<u64> = (1..HEALTH_SELF_TEST_DRAWS as u64).collect();
+        values.push(u64::MAX);
+        let instruction = HealthChecked::new("RDSEED", Sequence(RefCell::new(values)));
+        assert!(matches!(
+            instruction.self_test(),
+            Err(Error::HardwareBroken("RDSEED"))
+        ));
+    }
+
+    #[test]
+    fn test_health_checked_repetition() -> Result<()> {
+        let instruction = HealthChecked::new("RDRAND", healthy_then(&[7, 8, 8, 9, 10]));
+        instruction.self_test()?;
+        assert_eq!(instruction.generate_u64()?, 7);
+        assert_eq!(instruction.generate_u64()?, 8);
+        assert!(matches!(
+            instruction.generate_u64(),
+            Err(Error::HardwareBroken("RDRAND"))
+        ));
+        // once broken, the instruction is not used again
+        assert!(matches!(
+            instruction.generate_u64(),
+            Err(Error::HardwareBroken("RDRAND"))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_health_checked_instruction_error() {
+        let instruction = HealthChecked::new("RDRAND", Unsupported);
+        assert!(matches!(
+            instruction.generate_u64(),
+            Err(Error::RdrandUnsupported)
+        ));
+    }
+
+    #[test]
+    fn test_jitter_health_tests_fire_at_default() -> Result<(), crate::health::Error> {
+        use crate::health::{Error, APT_WINDOW};
+
+        // a stuck timer fails the repetition count test within 401 samples
+        let mut tests = HealthTests::new(DEFAULT_JITTER_MIN_ENTROPY)?;
+        let result = (0..401).try_for_each(|_| tests.process(true));
+        assert_eq!(result, Err(Error::RepetitionCount));
+
+        // 255 ones for every zero passes the repetition count test but not the proportion
+        let mut tests = HealthTests::new(DEFAULT_JITTER_MIN_ENTROPY)?;
+        let result = (0..APT_WINDOW).try_for_each(|i| tests.process(i % 256 != 255));
+        assert_eq!(result, Err(Error::AdaptiveProportion));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jitter_health_kept_across_seedings() -> Result<()> {
+        let combined = Combined::default();
+        combined.seed()?;
+
+        // a failure seen by the health tests stays for every later seeding
+        {
+            #[allow(clippy::unwrap_used)]
+            let mut health = combined.cpujitter.raw.health.lock().unwrap();
+            let result = (0..401).try_for_each(|_| health.process(true));
+            assert!(result.is_err());
+        }
+        for _ in 0..2 {
+            assert!(matches!(
+                combined.seed(),
+                Err(Error::SourcePolicyNotSatisfied(_, failed)) if failed.contains("Repetition Count")
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_cpu_source() -> Result<()> {
+        use HardwarePolicy::{Require, Skip};
+
+        assert!(matches!(
+            select_cpu_source(true, true, true, Require)?,
+            Some(Source::Rdseed)
+        ));
+        assert!(matches!(
+            select_cpu_source(false, true, true, Require)?,
+            Some(Source::Rdrand)
+        ));
+        assert!(matches!(
+            select_cpu_source(true, false, true, Require)?,
+            Some(Source::Rdrand)
+        ));
+        assert!(select_cpu_source(true, false, false, Skip)?.is_none());
+        assert!(select_cpu_source(false, false, false, Skip)?.is_none());
+        assert!(matches!(
+            select_cpu_source(true, false, false, Require),
+            Err(Error::RdseedUnsupported)
+        ));
+        assert!(matches!(
+            select_cpu_source(false, false, false, Require),
+            Err(Error::RdrandUnsupported)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hardware_sources_detected() {
+        assert_eq!(
+            matches!(RdRand {}.generate_u64(), Err(Error::RdrandUnsupported)),
+            !rdrand_supported()
+        );
+        assert_eq!(
+            matches!(RdSeed {}.generate_u64(), Err(Error::RdseedUnsupported)),
+            !rdseed_supported()
+        );
+    }
+
+    #[test]
+    fn test_uniform_below_rejects_biased_values() -> Result<()> {
+        // 2^64 mod 3 == 1, so a product with zero low bits must be rejected.
+        let generator = Sequence(RefCell::new(vec![0, u64::MAX]));
+        assert_eq!(uniform_below(&generator, 3)?, 2);
+        assert!(generator.0.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_uniform_below_edges() -> Result<()> {
+        let generator = SplitMix64::new(1);
+        assert!(matches!(
+            uniform_below(&generator, 0),
+            Err(Error::EmptyRange)
+        ));
+        for _ in 0..1000 {
+            assert_eq!(uniform_below(&generator, 1)?, 0);
+            assert!(uniform_below(&generator, u64::MAX)? < u64::MAX);
+        }
+        Ok(())
+    }
+
+    /// Chi-square goodness of fit against the uniform distribution.
+    ///
+    /// The critical value uses the Wilson-Hilferty approximation at z = 4.
+    fn assert_uniform(
+        generator: &dyn Generator,
+        n: u64,
+        samples_per_bucket: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut buckets = vec![0u64; usize::try_from(n)?];
+        for _ in 0..n * samples_per_bucket {
+            buckets[usize::try_from(uniform_below(generator, n)?)?] += 1;
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let expected = samples_per_bucket as f64;
+        #[allow(clippy::cast_precision_loss)]
+        let chi_square: f64 = buckets
+            .iter()
+            .map(|&observed| (observed as f64 - expected).powi(2) / expected)
+            .sum();
+
+        #[allow(clippy::cast_precision_loss)]
+        let df = (n - 1) as f64;
+        let critical = df * (1.0 - 2.0 / (9.0 * df) + 4.0 * (2.0 / (9.0 * df)).sqrt()).powi(3);
+        assert!(
+            chi_square < critical,
+            "chi-square {chi_square} exceeds {critical} for n = {n}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_uniform_below_bundled_alphabets() -> Result<(), Box<dyn std::error::Error>> {
+        let generator = SplitMix64::new(0x6b65_7967_656e);
+        for builtin in [
+            Builtin::WordsFi,
+            Builtin::Commonsafe,
+            Builtin::Normal,
+            Builtin::Ascii,
+            Builtin::Assembly,
+        ] {
+            let alphabet: Box<dyn Alphabet> = builtin.into();
+            assert_uniform(&generator, alphabet.count() as u64, 64)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_uniform_below_awkward_sizes() -> Result<(), Box<dyn std::error::Error>> {
+        let generator = SplitMix64::new(42);
+        for n in [2, 3, 5, 7, 10, 100, 1000, 6007] {
+            assert_uniform(&generator, n, 256)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_policy_from_str() {
+        assert_eq!("all".parse(), Ok(SourcePolicy::All));
+        assert_eq!("os-plus-one".parse(), Ok(SourcePolicy::OsPlusOne));
+        assert_eq!("2".parse(), Ok(SourcePolicy::AtLeast(2)));
+        assert!("0".parse::<SourcePolicy>().is_err());
+        assert!("some".parse::<SourcePolicy>().is_err());
+        assert_eq!(SourcePolicy::AtLeast(2).to_string(), "2");
+    }
+
+    /// Seed material of the OS, RDRAND and CPU jitter, with the OS source failing.
+    fn draws_without_os() -> Vec<Draw> {
+        vec![
+            (Source::Os, Err(Error::OsRandNotReady)),
+            (Source::Rdrand, Ok(Zeroizing::new(vec![1; 64]))),
+            (Source::Cpujitter, Ok(Zeroizing::new(vec![2; 64]))),
+        ]
+    }
+
+    #[test]
+    fn test_combine_draws_degraded() -> Result<()> {
+        let combined = combine_draws(SourcePolicy::AtLeast(2), draws_without_os())?;
+        assert_eq!(combined.contributors, [Source::Rdrand, Source::Cpujitter]);
+        assert_eq!(combined.seed[..64], [1; 64]);
+        assert_eq!(combined.seed[64..], [2; 64]);
+        assert!(matches!(
+            combined.failures[..],
+            [(Source::Os, Error::OsRandNotReady)]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_combine_draws_policy_not_satisfied() {
+        for policy in [
+            SourcePolicy::All,
+            SourcePolicy::AtLeast(3),
+            SourcePolicy::OsPlusOne,
+        ] {
+            assert!(matches!(
+                combine_draws(policy, draws_without_os()),
+                Err(Error::SourcePolicyNotSatisfied(p, _)) if p == policy
+            ));
+        }
+
+        let only_os = vec![
+            (Source::Os, Ok(Zeroizing::new(vec![0; 64]))),
+            (Source::Cpujitter, Err(Error::CpuJitterFailed)),
+        ];
+        assert!(combine_draws(SourcePolicy::OsPlusOne, only_os).is_err());
+    }
+
+    #[test]
+    fn test_combine_draws_all_sources() -> Result<()> {
+        for policy in [
+            SourcePolicy::All,
+            SourcePolicy::AtLeast(3),
+            SourcePolicy::OsPlusOne,
+        ] {
+            let mut draws = draws_without_os();
+            draws[0].1 = Ok(Zeroizing::new(vec![0; 64]));
+            let combined = combine_draws(policy, draws)?;
+            assert_eq!(combined.seed.len(), 3 * 64);
+            assert!(combined.failures.is_empty());
+        }
+        Ok(())
+    }
+
+    /// Requests served since the combined DRBG was last seeded.
+    fn requests_since_seeding(generator: &CombinedDrbg) -> Option<u64> {
+        #[allow(clippy::unwrap_used)]
+        let state = generator.state.lock().unwrap();
+        state.as_ref().map(|seeded| seeded.requests)
+    }
+
+    #[test]
+    fn test_reseed_due() -> Result<()> {
+        let policy = ReseedPolicy {
+            requests: 2,
+            bytes: 24,
+            interval: Duration::from_secs(60),
+        };
+        let mut instance = Instance::new(Mechanism::default().instantiate(&[0; 48], &[], &[])?);
+        assert!(!instance.reseed_due(&policy));
+        instance.requests = 2;
+        assert!(instance.reseed_due(&policy));
+        instance.requests = 0;
+        instance.bytes = 24;
+        assert!(instance.reseed_due(&policy));
+        instance.bytes = 0;
+        assert!(instance.reseed_due(&ReseedPolicy {
+            interval: Duration::ZERO,
+            ..policy
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_combined_drbg_reseeds() -> Result<()> {
+        let generator = CombinedDrbg::new(Combined {
+            reseed: ReseedPolicy {
+                requests: 2,
+                ..ReseedPolicy::default()
+            },
+            ..Combined::default()
+        });
+        assert_eq!(requests_since_seeding(&generator), None);
+
+        let values = [
+            generator.generate_u64()?,
+            generator.generate_u64()?,
+            generator.generate_u64()?,
+        ];
+        assert_ne!(values[0], values[1]);
+        assert_ne!(values[1], values[2]);
+        // the third request reseeded the DRBG
+        assert_eq!(requests_since_seeding(&generator), Some(1));
+
+        generator.request_reseed();
+        generator.generate_u64()?;
+        assert_eq!(requests_since_seeding(&generator), Some(1));
+        assert!(!generator.reseed_requested.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_bytes_from_u64() -> Result<()> {
+        let generator = Sequence(RefCell::new(vec![
+            0x0102_0304_0506_0708,
+            0x090a_0b0c_0d0e_0f10,
+        ]));
+        let mut bytes = [0; 11];
+        generator.try_fill_bytes(&mut bytes)?;
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_combined_drbg_fill_bytes() -> Result<()> {
+        let generator = CombinedDrbg::new(Combined::default());
+        let mut bytes = vec![0; 2 * drbg::MAX_BYTES_PER_REQUEST + 1];
+        generator.try_fill_bytes(&mut bytes)?;
+        // one request for each part of at most the maximum request size
+        assert_eq!(requests_since_seeding(&generator), Some(3));
+        assert!(bytes[2 * drbg::MAX_BYTES_PER_REQUEST - 8..]
+            .iter()
+            .any(|&byte| byte != 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_contributes() -> Result<(), Box<dyn std::error::Error>> {
+        let path = TempPath::new("contributor");
+        std::fs::write(&path, [[9; 64], [10; 64]].concat())?;
+        let combined = Combined {
+            external: vec![External::File(path.to_path_buf()).open()?],
+            ..Combined::default()
+        };
+        // each seeding reads further into the file, until it runs out
+        let seed = combined.seed()?;
+        assert_eq!(seed[seed.len() - 64..], [9; 64]);
+        let seed = combined.seed()?;
+        assert_eq!(seed[seed.len() - 64..], [10; 64]);
+        assert!(matches!(
+            combined.seed(),
+            Err(Error::SourcePolicyNotSatisfied(_, failed)) if failed.contains("ran out of data")
+        ));
+
+        let combined = Combined {
+            external: vec![External::File(path.to_path_buf()).open()?],
+            ..Combined::default()
+        };
+        let generator = Source::External.generator(combined)?;
+        assert_eq!(generator.generate_u64()?, 0x0909_0909_0909_0909);
+        assert!(matches!(
+            Source::External.generator(Combined::default()),
+            Err(Error::ExternalSourceCount(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_paranoid_fill_instantiates_per_value() -> Result<(), Box<dyn std::error::Error>> {
+        let path = TempPath::new("paranoid-fill");
+        std::fs::write(&path, [[9; 64], [10; 64]].concat())?;
+        let combined = Combined {
+            external: vec![External::File(path.to_path_buf()).open()?],
+            ..Combined::default()
         };
-        source.generate_u64()
+        // two 8-byte chunks take two seedings, which use up the file
+        let mut dest = [0u8; 16];
+        combined.try_fill_bytes(&mut dest)?;
+        assert_ne!(dest[..8], dest[8..]);
+        assert!(matches!(
+            combined.generate_u64(),
+            Err(Error::SourcePolicyNotSatisfied(_, failed)) if failed.contains("ran out of data")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_os_rand_ready() -> Result<()> {
+        // the kernel random number generator is long initialized when tests run
+        check_os_rand_ready()?;
+        assert!(OS_RAND_READY.load(Ordering::Relaxed));
+        check_os_rand_ready()
+    }
+
+    #[test]
+    fn test_personalization_string_unique() {
+        let strings: std::collections::HashSet<Vec<u8>> = (0..1000)
+            .map(|_| generate_personalization_string().to_vec())
+            .collect();
+        assert_eq!(strings.len(), 1000);
+    }
+
+    #[test]
+    fn test_personalization_string_fields() {
+        let personalization_string = generate_personalization_string();
+        assert!(personalization_string.starts_with(b"kissa123"));
+        let pid = std::process::id().to_le_bytes();
+        assert_eq!(personalization_string[16..20], pid);
+        // the hostname is the last field
+        let hostname = hostname();
+        assert!(personalization_string.ends_with(hostname.as_bytes()));
+    }
+
+    #[test]
+    fn test_seeded() -> Result<()> {
+        assert!(matches!(
+            Seeded::new(&[0; 48], false),
+            Err(Error::SeededNotAllowed)
+        ));
+        assert!(matches!(
+            Seeded::new(&[0; 47], true),
+            Err(Error::Drbg(drbg::Error::SeedTooShort(47)))
+        ));
+
+        let values = |seed: &[u8]| -> Result<Vec<u64>> {
+            let generator = Seeded::new(seed, true)?;
+            (0..4).map(|_| generator.generate_u64()).collect()
+        };
+        assert_eq!(values(&[0; 48])?, values(&[0; 48])?);
+        assert_ne!(values(&[0; 48])?, values(&[1; 48])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_file_replaced() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = TempPath::new("combined");
+        let path = directory.join("seed");
+        // a new seed file for each run, as in separate processes
+        let combined = |paranoid| Combined {
+            seed_file: Some(SeedFile::new(&path)),
+            paranoid,
+            ..Combined::default()
+        };
+
+        CombinedDrbg::new(combined(false)).generate_u64()?;
+        let seed_file = SeedFile::new(&path);
+        let first = seed_file.read()?.ok_or("seed file not written")?;
+        assert_eq!(first.len(), seedfile::SEED_SIZE);
+
+        let paranoid = combined(true);
+        paranoid.generate_u64()?;
+        let second = seed_file.read()?.ok_or("seed file not written")?;
+        assert_ne!(first, second);
+
+        // the paranoid mode replaces the seed file once, not for every value
+        paranoid.generate_u64()?;
+        assert_eq!(seed_file.read()?, Some(second));
+        Ok(())
     }
 }
@@ -0,0 +1,86 @@
+//! Adapters to the `rand_core` traits.
+//!
+//! [`RngAdapter`] lets the keygen sources be used wherever the Rust crypto ecosystem takes a
+//! `rand_core::RngCore`, for example to generate keys with other crates. Only the sources meant
+//! for cryptographic use, OS random and the combined DRBG, are marked `CryptoRng`.
+//!
+//! ```rust
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! use keygen::random::OsRand;
+//! use keygen::rng::RngAdapter;
+//! use rand_core::RngCore;
+//!
+//! let mut rng = RngAdapter(OsRand {});
+//! let mut key = [0u8; 32];
+//! rng.try_fill_bytes(&mut key)?;
+//! # Ok(()) }
+//! ```
+
+use crate::random::{Combined, CombinedDrbg, Generator, OsRand};
+use rand_core::{impls, CryptoRng, RngCore};
+
+/// A keygen [`Generator`] as a `rand_core::RngCore`.
+///
+/// The infallible methods of `RngCore` panic if the source fails, use
+/// [`RngCore::try_fill_bytes`] to handle the error instead.
+pub struct RngAdapter<G>(pub G);
+
+impl<G: Generator> RngCore for RngAdapter<G> {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.try_fill_bytes(dest).map_err(rand_core::Error::new)
+    }
+}
+
+impl CryptoRng for RngAdapter<OsRand> {}
+impl CryptoRng for RngAdapter<Combined> {}
+impl CryptoRng for RngAdapter<CombinedDrbg> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{self, Seeded};
+
+    #[test]
+    fn test_matches_generator() -> Result<(), Box<dyn std::error::Error>> {
+        let seed = [7; 48];
+        let generator = Seeded::new(&seed, true)?;
+        let mut rng = RngAdapter(Seeded::new(&seed, true)?);
+
+        assert_eq!(
+            rng.next_u64(),
+            u64::from_le_bytes(generator.generate_u64()?.to_be_bytes())
+        );
+        let mut expected = [0; 100];
+        generator.try_fill_bytes(&mut expected)?;
+        let mut bytes = [0; 100];
+        rng.try_fill_bytes(&mut bytes)?;
+        assert_eq!(bytes, expected);
+        Ok(())
+    }
+
+    struct Failing;
+
+    impl Generator for Failing {
+        fn generate_u64(&self) -> random::Result<u64> {
+            Err(random::Error::RdrandUnsupported)
+        }
+    }
+
+    #[test]
+    fn test_error_passed_on() {
+        let result = RngAdapter(Failing).try_fill_bytes(&mut [0; 8]);
+        assert!(result.is_err_and(|error| error.inner().is::<random::Error>()));
+    }
+}
@@ -0,0 +1,201 @@
+//! Persistent seed file.
+//!
+//! Like the random-seed of systemd, the file carries DRBG output from one run to the next. It is
+//! mixed into the DRBG as additional input and then replaced with fresh output, so that a freshly
+//! booted machine does not depend only on what the sources give at that moment. The file is
+//! replaced atomically and is readable by the owner only.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Seed file I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unable to locate the seed file, neither XDG_STATE_HOME nor HOME is set")]
+    NoStateDirectory,
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Size of the seed written to the file, in bytes.
+pub const SEED_SIZE: usize = 64;
+
+/// Largest part of the file read back.
+const MAX_READ: u64 = 4096;
+
+/// Seed file location, `$XDG_STATE_HOME/keygen/seed` or `$HOME/.local/state/keygen/seed`.
+fn default_path(state_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let state_home = state_home
+        .filter(|dir| Path::new(dir).is_absolute())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_home.join("keygen").join("seed"))
+}
+
+#[derive(Debug, Clone)]
+pub struct SeedFile {
+    path: PathBuf,
+    /// Set on the first use, shared by the clones
+    used: Arc<AtomicBool>,
+}
+
+impl SeedFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            used: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The seed file at the default location under the XDG state directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if neither `XDG_STATE_HOME` nor `HOME` is set.
+    pub fn default_location() -> Result<Self> {
+        default_path(std::env::var_os("XDG_STATE_HOME"), std::env::var_os("HOME"))
+            .map(Self::new)
+            .ok_or(Error::NoStateDirectory)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true the first time it is called on this seed file or a clone of it, so that the
+    /// file is read and replaced once per process however many DRBGs are instantiated.
+    #[must_use]
+    pub fn first_use(&self) -> bool {
+        !self.used.swap(true, Ordering::SeqCst)
+    }
+
+    /// Reads the seed, or `None` if there is no seed file yet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file exists but can not be read.
+    pub fn read(&self) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut seed = Zeroizing::new(Vec::with_capacity(SEED_SIZE));
+        file.take(MAX_READ).read_to_end(&mut seed)?;
+        Ok(Some(seed))
+    }
+
+    /// Replaces the seed file atomically with a new seed readable by the owner only.
+    ///
+    /// The seed is written to a temporary file in the same directory, synced to disk and then
+    /// renamed over the old file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory or the file can not be written.
+    pub fn write(&self, seed: &[u8]) -> Result<()> {
+        let directory = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(directory)?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let result = (|| {
+            let mut file = options.open(&temporary)?;
+            file.write_all(seed)?;
+            file.sync_all()?;
+            fs::rename(&temporary, &self.path)
+        })();
+        if result.is_err() {
+            // Best effort, the error of the write is the one reported
+            let _ = fs::remove_file(&temporary);
+        }
+        result?;
+
+        // Make the rename itself durable
+        #[cfg(unix)]
+        File::open(directory)?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempPath;
+
+    /// A seed file in a new directory, removed when the guard is dropped.
+    fn temporary_seed_file(name: &str) -> (TempPath, SeedFile) {
+        let directory = TempPath::new(name);
+        let seed_file = SeedFile::new(directory.join("state").join("seed"));
+        (directory, seed_file)
+    }
+
+    #[test]
+    fn test_default_path() {
+        assert_eq!(
+            default_path(Some("/state".into()), Some("/home/kissa".into())),
+            Some(PathBuf::from("/state/keygen/seed"))
+        );
+        // a relative XDG_STATE_HOME is ignored, as the specification says
+        assert_eq!(
+            default_path(Some("state".into()), Some("/home/kissa".into())),
+            Some(PathBuf::from("/home/kissa/.local/state/keygen/seed"))
+        );
+        assert_eq!(default_path(None, None), None);
+    }
+
+    #[test]
+    fn test_missing_seed_file() -> Result<()> {
+        let (_directory, seed_file) = temporary_seed_file("missing");
+        assert_eq!(seed_file.read()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_and_read() -> Result<()> {
+        let (_directory, seed_file) = temporary_seed_file("write");
+        seed_file.write(&[1; SEED_SIZE])?;
+        seed_file.write(&[2; SEED_SIZE])?;
+        assert_eq!(seed_file.read()?.as_deref(), Some(&vec![2; SEED_SIZE]));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(seed_file.path())?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // no temporary files are left behind
+        let directory = seed_file.path().parent().unwrap_or_else(|| Path::new("."));
+        assert_eq!(fs::read_dir(directory)?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_use() {
+        let seed_file = SeedFile::new("seed");
+        let clone = seed_file.clone();
+        assert!(seed_file.first_use());
+        assert!(!clone.first_use());
+        assert!(SeedFile::new("seed").first_use());
+    }
+}
@@ -0,0 +1,58 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Deterministic values from xorshift64, for test data that only needs to look random.
+pub fn xorshift64() -> impl Iterator<Item = u64> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    std::iter::repeat_with(move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    })
+}
+
+/// A path under the system temporary directory, unique to the test process. Whatever is at the
+/// path is removed when the guard is dropped, also when an assertion fails.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Reserves the path for `name`, removing anything left by an earlier run.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("keygen-test-{}-{name}", std::process::id()));
+        remove(&path);
+        Self(path)
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+/// Removes a file, socket or directory tree, if there is one.
+fn remove(path: &Path) {
+    let _ = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => Ok(()),
+    };
+}
@@ -0,0 +1,396 @@
+//! User supplied entropy.
+//!
+//! Physical dice rolls, coin flips or free text typed by the user, together with the time
+//! between keystrokes, are mixed into the combined DRBG as additional input. This lets a key
+//! ceremony show that no single electronic source controls the result. Only the physical
+//! randomness is credited, assuming fair dice and coins: log2(sides) bits per roll and one bit
+//! per flip. Text and keystroke timings are credited zero bits.
+
+use clap::ValueEnum;
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+use zeroize::Zeroizing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid die roll '{0}', expected a number from 1 to {1}")]
+    InvalidRoll(String, u32),
+    #[error("Invalid coin flip '{0}', expected h, t, 0 or 1")]
+    InvalidFlip(char),
+    #[error("Dice must have from 2 to 256 sides, not {0}")]
+    InvalidSides(u32),
+    #[error("Only {0:.1} bits of user entropy were credited, {1} bits are required")]
+    NotEnoughEntropy(f64, u32),
+    #[error("Unable to read user entropy: {0}")]
+    Io(#[from] io::Error),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What the user enters
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Dice rolls, credited log2(sides) bits each
+    Dice,
+    /// Coin flips as h/t or 1/0, credited one bit each
+    Coins,
+    /// Free text, mixed in but credited zero bits
+    Text,
+}
+
+/// Entropy entered by the user and the time between the keystrokes.
+pub struct UserEntropy {
+    kind: Kind,
+    sides: u32,
+    samples: Zeroizing<Vec<u8>>,
+    timings: Zeroizing<Vec<u8>>,
+    count: usize,
+    last_keystroke: Option<Instant>,
+}
+
+impl UserEntropy {
+    /// Starts collecting input of the given kind, `sides` being the number of sides of the dice.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if dice have fewer than 2 or more than 256 sides.
+    pub fn new(kind: Kind, sides: u32) -> Result<Self> {
+        if kind == Kind::Dice && !(2..=256).contains(&sides) {
+            return Err(Error::InvalidSides(sides));
+        }
+
+        Ok(Self {
+            kind,
+            sides,
+            samples: Zeroizing::new(Vec::new()),
+            timings: Zeroizing::new(Vec::new()),
+            count: 0,
+            last_keystroke: None,
+        })
+    }
+
+    /// Validates a line of input and adds it. Nothing is added from an invalid line.
+    ///
+    /// Dice rolls are separated by whitespace or commas; with at most 9 sides the digits may
+    /// also be written together. Coin flips are single characters, whitespace is ignored.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the line contains an invalid roll or flip.
+    pub fn add_line(&mut self, line: &str) -> Result<()> {
+        let samples: Vec<u8> = match self.kind {
+            Kind::Dice => parse_rolls(line, self.sides)?,
+            Kind::Coins => line
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| match c.to_ascii_lowercase() {
+                    'h' | '1' => Ok(1),
+                    't' | '0' => Ok(0),
+                    _ => Err(Error::InvalidFlip(c)),
+                })
+                .collect::<Result<_>>()?,
+            Kind::Text => line.as_bytes().to_vec(),
+        };
+
+        self.count += match self.kind {
+            Kind::Text => usize::from(!line.is_empty()),
+            _ => samples.len(),
+        };
+        self.samples.extend_from_slice(&samples);
+        if self.kind == Kind::Text {
+            self.samples.push(b'\n');
+        }
+        Ok(())
+    }
+
+    /// Records the time of a keystroke, as nanoseconds since the previous one.
+    pub fn add_keystroke(&mut self, at: Instant) {
+        if let Some(previous) = self.last_keystroke.replace(at) {
+            #[allow(clippy::cast_possible_truncation)]
+            let nanos = at.duration_since(previous).as_nanos() as u64;
+            self.timings.extend_from_slice(&nanos.to_le_bytes());
+        }
+    }
+
+    /// Number of rolls, flips or lines of text entered.
+    #[must_use]
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Bits of entropy credited to the input.
+    #[must_use]
+    pub fn credited_bits(&self) -> f64 {
+        match self.kind {
+            Kind::Dice => self.count as f64 * f64::from(self.sides).log2(),
+            Kind::Coins => self.count as f64,
+            Kind::Text => 0.0,
+        }
+    }
+
+    /// Checks that at least `bits` bits have been credited.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if fewer bits have been credited.
+    pub fn require(&self, bits: u32) -> Result<()> {
+        if self.credited_bits() < f64::from(bits) {
+            return Err(Error::NotEnoughEntropy(self.credited_bits(), bits));
+        }
+        Ok(())
+    }
+
+    /// The input and the keystroke timings, encoded as DRBG additional input.
+    #[must_use]
+    pub fn additional_input(&self) -> Zeroizing<Vec<u8>> {
+        let mut input = Zeroizing::new(Vec::with_capacity(
+            32 + self.samples.len() + self.timings.len(),
+        ));
+        input.extend_from_slice(b"keygen user entropy");
+        input.push(self.kind as u8);
+        input.extend_from_slice(&self.sides.to_le_bytes());
+        for field in [&self.samples, &self.timings] {
+            input.extend_from_slice(&(field.len() as u64).to_le_bytes());
+            input.extend_from_slice(field);
+        }
+        input
+    }
+
+    /// Reads lines until an empty line or the end of input, reporting progress on stderr.
+    ///
+    /// On a Linux terminal every keystroke is timed; otherwise each line is timed as it
+    /// arrives. Invalid lines are reported and skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if reading the input fails.
+    pub fn read_interactive(&mut self) -> Result<()> {
+        eprintln!("{}", self.prompt());
+
+        #[cfg(target_os = "linux")]
+        if let Some(_terminal) = terminal::Unbuffered::new() {
+            return self.read_keystrokes();
+        }
+
+        for line in io::stdin().lock().lines() {
+            self.add_keystroke(Instant::now());
+            if !self.accept(&line?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn prompt(&self) -> String {
+        let input = match self.kind {
+            Kind::Dice => format!("dice rolls (1-{})", self.sides),
+            Kind::Coins => "coin flips (h/t)".to_string(),
+            Kind::Text => "text".to_string(),
+        };
+        format!("Enter {input}, finish with an empty line:")
+    }
+
+    /// Adds a line typed by the user. Returns false at the empty line that ends the input.
+    fn accept(&mut self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            return false;
+        }
+        match self.add_line(line) {
+            Ok(()) => eprintln!(
+                "{} entered, {:.1} bits credited",
+                self.count,
+                self.credited_bits()
+            ),
+            Err(error) => eprintln!("Line ignored: {error}"),
+        }
+        true
+    }
+
+    /// Reads the unbuffered terminal a byte at a time, timing every keystroke.
+    #[cfg(target_os = "linux")]
+    fn read_keystrokes(&mut self) -> Result<()> {
+        use std::io::Read;
+
+        let mut stdin = io::stdin().lock();
+        let mut line = Zeroizing::new(Vec::new());
+        let mut byte = [0u8; 1];
+
+        while stdin.read(&mut byte)? == 1 {
+            self.add_keystroke(Instant::now());
+            match byte[0] {
+                b'\n' | b'\r' => {
+                    eprintln!();
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    if !self.accept(&text) {
+                        break;
+                    }
+                }
+                // backspace and delete
+                0x08 | 0x7f => {
+                    if line.pop().is_some() {
+                        eprint!("\x08 \x08");
+                    }
+                }
+                // end of input
+                0x04 => break,
+                // Ctrl-C
+                0x03 => return Err(io::Error::from(io::ErrorKind::Interrupted).into()),
+                other => {
+                    line.push(other);
+                    eprint!("{}", char::from(other));
+                }
+            }
+            io::stderr().flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses dice rolls of a line into values from 0 to sides - 1.
+fn parse_rolls(line: &str, sides: u32) -> Result<Vec<u8>> {
+    let mut rolls = Vec::new();
+    for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+        let values: Vec<&str> = if sides <= 9 && token.chars().all(|c| c.is_ascii_digit()) {
+            // single digit rolls may be written together
+            (0..token.len()).map(|i| &token[i..=i]).collect()
+        } else if token.is_empty() {
+            Vec::new()
+        } else {
+            vec![token]
+        };
+
+        for value in values {
+            match value.parse::<u32>() {
+                Ok(roll) if (1..=sides).contains(&roll) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    rolls.push((roll - 1) as u8);
+                }
+                _ => return Err(Error::InvalidRoll(value.to_string(), sides)),
+            }
+        }
+    }
+    Ok(rolls)
+}
+
+/// Terminal input without line buffering.
+#[cfg(target_os = "linux")]
+mod terminal {
+    /// Switches standard input out of canonical mode and restores it when dropped. Echo is
+    /// handled by the reader, so that backspace can be shown.
+    pub struct Unbuffered(libc::termios);
+
+    impl Unbuffered {
+        /// Returns `None` if standard input is not a terminal.
+        pub fn new() -> Option<Self> {
+            // SAFETY: termios is plain old data, all zeroes is a valid value
+            let mut original: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: the termios is valid for writes
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+                return None;
+            }
+
+            let mut unbuffered = original;
+            // signals are off too, so that Ctrl-C does not leave the terminal unbuffered
+            unbuffered.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            unbuffered.c_cc[libc::VMIN] = 1;
+            unbuffered.c_cc[libc::VTIME] = 0;
+            // SAFETY: the termios is a valid setting read from the terminal and modified
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &unbuffered) } != 0 {
+                return None;
+            }
+
+            Some(Self(original))
+        }
+    }
+
+    impl Drop for Unbuffered {
+        fn drop(&mut self) {
+            // SAFETY: restores the setting read from the terminal
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dice() -> Result<()> {
+        let mut entropy = UserEntropy::new(Kind::Dice, 6)?;
+        entropy.add_line("316524")?;
+        entropy.add_line("1, 2 6")?;
+        assert_eq!(entropy.count(), 9);
+        assert_eq!(entropy.samples[..], [2, 0, 5, 4, 1, 3, 0, 1, 5]);
+        assert!((entropy.credited_bits() - 9.0 * 6f64.log2()).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_rolls() -> Result<()> {
+        let mut entropy = UserEntropy::new(Kind::Dice, 6)?;
+        for line in ["1237", "0", "x", "-1"] {
+            assert!(matches!(
+                entropy.add_line(line),
+                Err(Error::InvalidRoll(_, 6))
+            ));
+        }
+        // nothing from an invalid line is added
+        assert_eq!(entropy.count(), 0);
+        assert!(entropy.samples.is_empty());
+
+        let mut d20 = UserEntropy::new(Kind::Dice, 20)?;
+        d20.add_line("20 1 13")?;
+        assert!(d20.add_line("21").is_err());
+        assert_eq!(d20.samples[..], [19, 0, 12]);
+
+        assert!(matches!(
+            UserEntropy::new(Kind::Dice, 1),
+            Err(Error::InvalidSides(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coins() -> Result<()> {
+        let mut entropy = UserEntropy::new(Kind::Coins, 0)?;
+        entropy.add_line("HhT t 10")?;
+        assert_eq!(entropy.samples[..], [1, 1, 0, 0, 1, 0]);
+        assert!((entropy.credited_bits() - 6.0).abs() < f64::EPSILON);
+        assert!(matches!(
+            entropy.add_line("hx"),
+            Err(Error::InvalidFlip('x'))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_is_not_credited() -> Result<()> {
+        let mut entropy = UserEntropy::new(Kind::Text, 0)?;
+        entropy.add_line("correct horse battery staple")?;
+        assert_eq!(entropy.count(), 1);
+        assert!(entropy.credited_bits().abs() < f64::EPSILON);
+        assert!(matches!(
+            entropy.require(1),
+            Err(Error::NotEnoughEntropy(_, 1))
+        ));
+        entropy.require(0)
+    }
+
+    #[test]
+    fn test_additional_input() -> Result<()> {
+        let mut first = UserEntropy::new(Kind::Coins, 0)?;
+        first.add_line("ht")?;
+        let mut second = UserEntropy::new(Kind::Coins, 0)?;
+        second.add_line("th")?;
+        assert_ne!(first.additional_input(), second.additional_input());
+
+        // keystroke timings are mixed in too
+        let start = Instant::now();
+        second.add_keystroke(start);
+        second.add_keystroke(start + std::time::Duration::from_millis(150));
+        let input = second.additional_input();
+        assert!(input.ends_with(&150_000_000u64.to_le_bytes()));
+        Ok(())
+    }
+}
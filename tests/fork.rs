@@ -0,0 +1,63 @@
+//! Forks the process and checks that the combined DRBG reseeds in every child.
+//!
+//! This is a separate test binary, so that no other tests run threads that could hold locks
+//! the forked children need.
+#![cfg(target_os = "linux")]
+
+use keygen::random::{Combined, CombinedDrbg, Generator};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::FromRawFd;
+
+/// Forks a child that sends its next value through a pipe, and returns the value.
+fn next_in_child(generator: &CombinedDrbg) -> Result<u64, Box<dyn Error>> {
+    let mut fds = [0; 2];
+    // SAFETY: the array has room for both descriptors
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    // SAFETY: the child only generates a value, writes it and exits
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        0 => {
+            // a failure leaves the pipe empty, which fails the read in the parent
+            if let Ok(value) = generator.generate_u64() {
+                let bytes = value.to_le_bytes();
+                // SAFETY: the buffer is valid for reads of its length
+                unsafe { libc::write(fds[1], bytes.as_ptr().cast(), bytes.len()) };
+            }
+            // SAFETY: exits the child without running the test harness
+            unsafe { libc::_exit(0) }
+        }
+        child => {
+            // SAFETY: the write end is not used by the parent
+            unsafe { libc::close(fds[1]) };
+            // SAFETY: the read end is owned by nothing else
+            let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+            let mut bytes = [0; 8];
+            let result = pipe.read_exact(&mut bytes);
+            // SAFETY: waits for the child created above
+            unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+            result?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+}
+
+#[test]
+fn test_forked_children_diverge() -> Result<(), Box<dyn Error>> {
+    let generator = CombinedDrbg::new(Combined::default());
+    // seeds the DRBG before forking, so that the children start from the same state
+    generator.generate_u64()?;
+
+    let first = next_in_child(&generator)?;
+    let second = next_in_child(&generator)?;
+    let parent = generator.generate_u64()?;
+
+    assert_ne!(first, second);
+    assert_ne!(first, parent);
+    assert_ne!(second, parent);
+    Ok(())
+}